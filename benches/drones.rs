@@ -0,0 +1,84 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Measures ticks per second with many drones.
+//!
+//! Run with `cargo bench`, and add `--features parallel` to use all cores.
+
+extern crate pistonpath;
+extern crate rand;
+
+use std::time::{Duration,Instant};
+use rand::{Rng,SeedableRng};
+use rand::rngs::SmallRng;
+use pistonpath::board::Board;
+use pistonpath::board::Tile::*;
+use pistonpath::drones::Drone;
+use pistonpath::scenario::Scenario;
+use pistonpath::sim::{Simulation,Settings,Arrival,Movement};
+
+const SIZE: i32 = 300;
+const MEASURE_FOR: Duration = Duration::from_secs(2);
+
+/// A large board with some walls, where drones that arrive are moved to a
+/// spawner in a corner so that the number of drones stays the same.
+fn scenario(drones: usize,  movement: Movement) -> Scenario {
+    let mut rng = SmallRng::seed_from_u64(drones as u64);
+    let mut board = Board::new(SIZE, SIZE, 1);
+    for x in (10..SIZE).step_by(20) {
+        for y in 0..SIZE-10 {
+            board[[x, y, 0]] = Wall;
+        }
+    }
+    board[[SIZE/2, SIZE/2, 0]] = Target;
+    for &corner in &[[0,0,0], [SIZE-1,0,0], [0,SIZE-1,0], [SIZE-1,SIZE-1,0]] {
+        board[corner] = Spawner;
+    }
+    let drones: Vec<Drone> = (0..drones).map(|_| loop {
+        let pos = [rng.gen_range(0.0, SIZE as f64 - 1.0),  rng.gen_range(0.0, SIZE as f64 - 1.0),  0.0];
+        let drone = Drone::new(pos);
+        if board[drone.tile()] == Open {
+            break drone;
+        }
+    }).collect();
+    let settings = Settings {
+        on_arrival: Arrival::Respawn,
+        spawn_cap: Some(0),
+        max_drones: drones.len(),
+        movement,
+        ..Settings::default()
+    };
+    Scenario{board,  drones,  settings,  ..Scenario::default()}
+}
+
+fn main() {
+    let parallel = if cfg!(feature="parallel") {"with"} else {"without"};
+    println!("{}x{} tiles, {} the parallel feature", SIZE, SIZE, parallel);
+    for &movement in &Movement::ALL {
+        for &drones in &[1_000, 10_000, 100_000] {
+            let mut sim = Simulation::new(scenario(drones, movement), 0);
+            let started = Instant::now();
+            let mut ticks = 0;
+            while started.elapsed() < MEASURE_FOR {
+                sim.tick();
+                ticks += 1;
+            }
+            let per_second = ticks as f64 / started.elapsed().as_secs_f64();
+            println!("{:>7} drones, {:>8} movement: {:>8.1} ticks per second",
+                     drones, movement.name(), per_second);
+        }
+    }
+}
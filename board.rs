@@ -0,0 +1,204 @@
+/* Copyright (C) 2015 Alexandru Cojocaru,
+ *               2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The square grid drones move on.
+
+use std::ops::{Neg,Index,IndexMut};
+use num::{Zero,One};
+use vecmath::vec3_add;
+use topology::{Topology,CostModel};
+
+
+#[derive(Clone,Copy, PartialEq,Eq, Debug)]
+pub enum Direction {North, South, East, West, Up, Down}
+use self::Direction::*;
+impl Direction {
+    pub const ALL: [Direction; 6] = [North, South, East, West, Up, Down];
+
+    /// Is generic so it can produce both floats and integers.
+    /// The third dimension is the floor.
+    pub fn unit_vector<T:Zero+One+Neg<Output=T>>(self) -> [T; 3] {
+        match self {
+            North => [T::zero(),       T::one(),        T::zero()      ],
+            South => [T::zero(),       T::one().neg(),  T::zero()      ],
+            East  => [T::one(),        T::zero(),       T::zero()      ],
+            West  => [T::one().neg(),  T::zero(),       T::zero()      ],
+            Up    => [T::zero(),       T::zero(),       T::one()       ],
+            Down  => [T::zero(),       T::zero(),       T::one().neg() ],
+        }
+    }
+    pub fn is_vertical(self) -> bool {
+        self == Up  ||  self == Down
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            North => "north",
+            South => "south",
+            East => "east",
+            West => "west",
+            Up => "up",
+            Down => "down",
+        }
+    }
+    /// Which way `to` is from `from`, if they are next to each other.
+    pub fn between(from: [i32; 3],  to: [i32; 3]) -> Option<Direction> {
+        Direction::ALL.iter().cloned().find(|&d| {
+            let v = d.unit_vector::<i32>();
+            (0..3).all(|i| from[i]+v[i] == to[i] )
+        })
+    }
+}
+
+
+#[derive(Clone,Copy, PartialEq,Eq, Debug)]
+pub enum Tile {
+    Wall,
+    Target,
+    Open,
+    /// Connects to stairs directly above or below.
+    Stairs,
+    /// Connects to elevators directly above or below.
+    Elevator,
+    /// Creates new drones, see `sim::Settings`.
+    Spawner,
+    /// Wheeled drones cannot move through it, see `kind::DroneKind`.
+    Mud,
+    /// Recharges drones with low battery, see `sim::Settings::battery`.
+    Charger,
+}
+use self::Tile::*; // use Wall instead of Tile::Wall
+impl Tile {
+    pub const ALL: [Tile; 8] = [Open, Wall, Target, Stairs, Elevator, Spawner, Mud, Charger];
+    pub fn name(self) -> &'static str {
+        match self {
+            Wall => "wall",
+            Target => "target",
+            Open => "open",
+            Stairs => "stairs",
+            Elevator => "elevator",
+            Spawner => "spawner",
+            Mud => "mud",
+            Charger => "charger",
+        }
+    }
+    /// Drones can only change floor between two tiles of the same type.
+    pub fn connects_vertically(&self,  other: &Tile) -> bool {
+        matches!((*self, *other),  (Stairs, Stairs) | (Elevator, Elevator))
+    }
+}
+
+
+/// A stack of equally sized floors.
+/// Positions are `[x, y, floor]`, with floor 0 at the bottom.
+#[derive(Clone, PartialEq,Eq, Debug)]
+pub struct Board {
+    pub width: i32,
+    pub height: i32,
+    pub tiles: Vec<Tile>,// floor by floor, row by row
+} impl Board {
+    pub fn new(width: i32,  height: i32,  floors: i32) -> Board {
+        Board {
+            width,
+            height,
+            tiles: vec![Open; (width*height*floors) as usize],
+        }
+    }
+    pub fn floors(&self) -> i32 {
+        self.tiles.len() as i32 / (self.width*self.height)
+    }
+    pub fn contains(&self,  p: [i32; 3]) -> bool {
+        p[0]>=0  &&  p[0]<self.width
+        && p[1]>=0  &&  p[1]<self.height
+        && p[2]>=0  &&  p[2]<self.floors()
+    }
+    pub fn get(&self,  p: [i32; 3]) -> Option<Tile> {
+        if self.contains(p) {Some(self[p])} else {None}
+    }
+    pub fn add_floor(&mut self) {
+        let len = self.tiles.len() + (self.width*self.height) as usize;
+        self.tiles.resize(len, Open);
+    }
+    /// Never removes the last floor.
+    pub fn remove_floor(&mut self) {
+        if self.floors() > 1 {
+            let len = self.tiles.len() - (self.width*self.height) as usize;
+            self.tiles.truncate(len);
+        }
+    }
+    /// Iterates over all positions in the box between `first` and `second` (inclusive)
+    pub fn positions(first: [i32; 3],  second: [i32; 3]) -> impl Iterator<Item=[i32; 3]> {
+        (first[2]..=second[2]).flat_map(move |z| (first[1]..=second[1]).flat_map(move |y|
+            (first[0]..=second[0]).map(move |x| [x,y,z] )
+        ))
+    }
+    fn tile_index(&self,  p: [i32; 3]) -> usize {
+        ((p[2]*self.height + p[1])*self.width + p[0]) as usize
+    }
+    /// The position of `tiles[index]`
+    pub fn position(&self,  index: usize) -> [i32; 3] {
+        let index = index as i32;
+        [index % self.width,  index / self.width % self.height,  index / (self.width*self.height)]
+    }
+} impl Index<[i32; 3]> for Board {
+    type Output = Tile;
+    fn index(&self,  p: [i32; 3]) -> &Tile {
+        &self.tiles[self.tile_index(p)]
+    }
+} impl IndexMut<[i32; 3]> for Board {
+    fn index_mut(&mut self,  p: [i32; 3]) -> &mut Tile {
+        let i = self.tile_index(p);
+        &mut self.tiles[i]
+    }
+}
+
+/// Tiles are connected to the four tiles around them,
+/// and stairs and elevators also to the floors above and below.
+impl Topology for Board {
+    type Node = [i32; 3];
+    fn node_count(&self) -> usize {
+        self.tiles.len()
+    }
+    fn index(&self,  p: [i32; 3]) -> usize {
+        self.tile_index(p)
+    }
+    fn neighbours<F: FnMut([i32; 3])>(&self,  from: [i32; 3],  mut f: F) {
+        let here = self[from];
+        for &dir in &Direction::ALL {
+            let to = vec3_add(from, dir.unit_vector());
+            match self.get(to) {
+                Some(ref there) if dir.is_vertical()  &&  !here.connects_vertically(there) => {},
+                Some(_) => f(to),
+                None => {}
+            }
+        }
+    }
+}
+
+
+/// All moves cost the same, except into or out of walls which are impossible.
+#[derive(Clone,Copy, Default, Debug)]
+pub struct UniformCost;
+impl CostModel<Board> for UniformCost {
+    fn cost(&self,  board: &Board,  from: [i32; 3],  to: [i32; 3]) -> Option<u32> {
+        if board[from] == Wall  ||  board[to] == Wall {None} else {Some(1)}
+    }
+    /// The manhattan distance, which is exact when there are no walls in the
+    /// way and both positions are on the same floor.
+    fn estimate(&self,  _: &Board,  from: [i32; 3],  to: [i32; 3]) -> u32 {
+        (0..3).map(|i| (from[i]-to[i]).unsigned_abs() ).sum()
+    }
+}
@@ -0,0 +1,194 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+//! Copying and pasting parts of a floor.
+
+use std::collections::HashMap;
+use std::fmt;
+use board::{Board,Tile};
+use kind::DroneKind;
+use scenario::{tile_char,tile_from_char};
+
+
+/// A rectangle of tiles, and the kind of drones the spawners among them create.
+///
+/// It's written as rows of the characters used in scenario files, and can
+/// be read back with `parse()`, but spawner kinds are not included.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Clipboard {
+    pub width: i32,
+    pub height: i32,
+    /// Row by row
+    tiles: Vec<(Tile, Option<DroneKind>)>,
+} impl Clipboard {
+    /// Copies the rectangle with `a` and `b` as opposite corners on the floor of `a`.
+    /// Both must be on the board.
+    pub fn copy(board: &Board,  kinds: &HashMap<[i32; 3], DroneKind>,  a: [i32; 3],  b: [i32; 3])
+    -> Clipboard {
+        let (x0, x1) = (a[0].min(b[0]), a[0].max(b[0]));
+        let (y0, y1) = (a[1].min(b[1]), a[1].max(b[1]));
+        let mut tiles = Vec::new();
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let p = [x, y, a[2]];
+                tiles.push((board[p], kinds.get(&p).cloned()));
+            }
+        }
+        Clipboard{width: x1-x0+1,  height: y1-y0+1,  tiles}
+    }
+
+    /// Reads rows of tile characters, which must all be equally long.
+    pub fn parse(text: &str) -> Result<Clipboard, String> {
+        let mut tiles = Vec::new();
+        let mut width = None;
+        let mut height = 0;
+        for row in text.lines().map(str::trim).filter(|row| !row.is_empty() ) {
+            let row = row.chars()
+                .map(|c| tile_from_char(c).ok_or_else(|| format!("unknown tile {:?}", c) ) )
+                .collect::<Result<Vec<Tile>,_>>()?;
+            if *width.get_or_insert(row.len()) != row.len() {
+                return Err(format!("row {} is {} tiles wide, not {}", height+1, row.len(), width.unwrap()));
+            }
+            tiles.extend(row.into_iter().map(|tile| (tile, None) ));
+            height += 1;
+        }
+        match width {
+            Some(width) if width > 0 => Ok(Clipboard{width: width as i32,  height,  tiles}),
+            _ => Err("no tiles".to_string()),
+        }
+    }
+
+    /// Where each tile goes when pasted with the top left corner at `at`,
+    /// including positions outside the board.
+    pub fn placed(&self,  at: [i32; 3]) -> impl Iterator<Item=([i32; 3], Tile, Option<DroneKind>)> + '_ {
+        let width = self.width;
+        self.tiles.iter().enumerate().map(move |(i, &(tile, kind))| {
+            let (x, y) = (i as i32 % width,  i as i32 / width);
+            ([at[0]+x, at[1]+y, at[2]], tile, kind)
+        })
+    }
+
+    /// Rebuilds the tiles with `from(x, y)` giving where in the current tiles
+    /// the new tile at x,y comes from.
+    fn rearrange<F: Fn(i32, i32) -> (i32, i32)>(&mut self,  width: i32,  height: i32,  from: F) {
+        let mut tiles = Vec::with_capacity(self.tiles.len());
+        for y in 0..height {
+            for x in 0..width {
+                let (fx, fy) = from(x, y);
+                tiles.push(self.tiles[(fy*self.width + fx) as usize]);
+            }
+        }
+        self.tiles = tiles;
+        self.width = width;
+        self.height = height;
+    }
+    /// Turns it 90 degrees clockwise.
+    pub fn rotate(&mut self) {
+        let height = self.height;
+        self.rearrange(self.height, self.width, |x, y| (y, height-1-x) );
+    }
+    /// Mirrors it left to right.
+    pub fn flip_horizontally(&mut self) {
+        let width = self.width;
+        self.rearrange(self.width, self.height, |x, y| (width-1-x, y) );
+    }
+    /// Mirrors it top to bottom.
+    pub fn flip_vertically(&mut self) {
+        let height = self.height;
+        self.rearrange(self.width, self.height, |x, y| (x, height-1-y) );
+    }
+}
+
+impl fmt::Display for Clipboard {
+    fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        for row in self.tiles.chunks(self.width as usize) {
+            let row: String = row.iter().map(|&(tile, _)| tile_char(tile) ).collect();
+            writeln!(fmtr, "{}", row)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three tiles wide and two high, with no symmetry.
+    const CLIP: &str = "#.+\n~SC\n";
+
+    #[test]
+    fn rotating_four_times_gives_the_original() {
+        let original = Clipboard::parse(CLIP).unwrap();
+        let mut clip = original.clone();
+        clip.rotate();
+        assert_eq!((clip.width, clip.height), (2, 3));
+        assert_eq!(clip.to_string(), "~#\nS.\nC+\n");
+        clip.rotate();
+        assert_eq!((clip.width, clip.height), (3, 2));
+        assert_eq!(clip.to_string(), "CS~\n+.#\n");
+        clip.rotate();
+        assert_eq!((clip.width, clip.height), (2, 3));
+        clip.rotate();
+        assert_eq!(clip, original);
+    }
+
+    #[test]
+    fn flipping_twice_gives_the_original() {
+        let original = Clipboard::parse(CLIP).unwrap();
+        let mut clip = original.clone();
+        clip.flip_horizontally();
+        assert_eq!(clip.to_string(), "+.#\nCS~\n");
+        clip.flip_horizontally();
+        assert_eq!(clip, original);
+        clip.flip_vertically();
+        assert_eq!(clip.to_string(), "~SC\n#.+\n");
+        clip.flip_vertically();
+        assert_eq!(clip, original);
+        // both flips are the same as rotating twice
+        clip.flip_horizontally();
+        clip.flip_vertically();
+        let mut rotated = original.clone();
+        rotated.rotate();
+        rotated.rotate();
+        assert_eq!(clip, rotated);
+    }
+
+    #[test]
+    fn spawner_kinds_follow_their_tiles() {
+        let mut board = Board::new(4, 3, 1);
+        board[[3,1,0]] = Tile::Spawner;
+        let mut kinds = HashMap::new();
+        kinds.insert([3,1,0], DroneKind::Flying);
+        let mut clip = Clipboard::copy(&board, &kinds, [3,2,0], [1,1,0]);
+        assert_eq!((clip.width, clip.height), (3, 2));
+        clip.rotate();
+        let spawners: Vec<_> = clip.placed([0,0,0]).filter(|&(_, tile, _)| tile == Tile::Spawner ).collect();
+        assert_eq!(spawners, vec![([1,2,0], Tile::Spawner, Some(DroneKind::Flying))]);
+    }
+
+    #[test]
+    fn display_can_be_parsed() {
+        let clip = Clipboard::parse(CLIP).unwrap();
+        assert_eq!(clip.to_string(), CLIP);
+        assert_eq!(Clipboard::parse(&clip.to_string()), Ok(clip));
+        assert_eq!(Clipboard::parse(" #.\n\n +~ \n").unwrap().to_string(), "#.\n+~\n");
+        assert!(Clipboard::parse("#.\n#\n").is_err());
+        assert!(Clipboard::parse("#x\n").is_err());
+        assert!(Clipboard::parse("\n").is_err());
+    }
+}
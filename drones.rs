@@ -0,0 +1,314 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Storing many drones so that updating and drawing them is fast.
+//!
+//! `Drones` keeps each field of `Drone` in its own vector, so that loops which
+//! only need a few fields don't read the others, and so that the vectors can
+//! be split into chunks that are updated in parallel.
+//! With the `parallel` feature, `map_indices()` and `for_each_chunk()` use
+//! all cores through rayon.
+
+use std::iter::FromIterator;
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+use kind::DroneKind;
+#[cfg(feature="parallel")]
+use rayon::prelude::*;
+
+
+/// The width and height of drones, in tiles.
+pub const DRONE_SIZE: f64 = 0.4;
+
+/// How many drones `for_each_chunk()` gives to each call.
+const CHUNK_SIZE: usize = 1024;
+
+
+/// The tile the center of a drone at `pos` is in.
+pub fn tile_of(pos: [f64; 3]) -> [i32; 3] {
+    [(pos[0] + DRONE_SIZE/2.0) as i32,
+     (pos[1] + DRONE_SIZE/2.0) as i32,
+     pos[2] as i32]
+}
+
+/// Random numbers for one drone in one tick, which don't depend on the order
+/// drones are updated in.
+pub fn drone_rng(seed: u64,  tick: u64,  id: u64) -> SmallRng {
+    // splitmix64, so that nearby ids and ticks give unrelated seeds
+    let mut x = seed ^ tick.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ id.wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    SmallRng::seed_from_u64(x ^ (x >> 31))
+}
+
+/// Calls `f` with every index below `len` and collects the results in order.
+#[cfg(feature="parallel")]
+pub fn map_indices<T,F>(len: usize,  f: F) -> Vec<T>
+where T: Send, F: Fn(usize)->T + Sync + Send {
+    (0..len).into_par_iter().map(f).collect()
+}
+/// Calls `f` with every index below `len` and collects the results in order.
+#[cfg(not(feature="parallel"))]
+pub fn map_indices<T,F>(len: usize,  f: F) -> Vec<T>
+where T: Send, F: Fn(usize)->T + Sync + Send {
+    (0..len).map(f).collect()
+}
+
+
+/// One drone, for creating drones and for code where speed doesn't matter.
+#[derive(Clone,Copy, PartialEq, Debug)]
+pub struct Drone {
+    /// Unique within a simulation, set by it.
+    pub id: u64,
+    /// The top left corner, the third value is the floor.
+    pub pos: [f64; 3],
+    /// Where it was before the last tick, for drawing it moving between tiles.
+    pub prev: [f64; 3],
+    /// In tiles per tick, only used with `Movement::Steering`.
+    pub vel: [f64; 2],
+    /// The tick it was created in.
+    pub born: u64,
+    /// How many tiles it has moved, including between floors.
+    pub steps: u32,
+    /// Whether it has reached the target and been counted in `Stats::arrived`.
+    pub arrived: bool,
+    pub kind: DroneKind,
+    /// How many more tiles it can move, if `Settings::battery` is set.
+    /// Anything above the capacity means a full battery.
+    pub energy: u32,
+    /// Whether it's going to or waiting at a charger instead of the target.
+    pub charging: bool,
+    /// Whether it couldn't reach where it's going after the last tick or path change.
+    pub trapped: bool,
+    /// The index in `Simulation::destinations` it was sent to instead of the target.
+    pub order: Option<u32>,
+} impl Drone {
+    /// Creates a wheeled drone
+    pub fn new(pos: [f64; 3]) -> Drone {
+        Drone {
+            id: 0,
+            pos,  prev: pos,  vel: [0.0, 0.0],
+            born: 0,  steps: 0,  arrived: false,
+            kind: DroneKind::default(),
+            energy: u32::MAX,  charging: false,
+            trapped: false,
+            order: None,
+        }
+    }
+    /// The index of the flow field it follows in `Simulation::paths`.
+    pub fn field(&self) -> usize {
+        field(self.kind, self.charging, self.order)
+    }
+    /// The tile the center of the drone is in.
+    pub fn tile(&self) -> [i32; 3] {
+        tile_of(self.pos)
+    }
+}
+
+fn field(kind: DroneKind,  charging: bool,  order: Option<u32>) -> usize {
+    let kinds = DroneKind::ALL.len();
+    match (charging, order) {
+        (true, _) => kinds + kind as usize,
+        (false, Some(order)) => (2 + order as usize)*kinds + kind as usize,
+        (false, None) => kind as usize,
+    }
+}
+
+
+/// All the drones in a simulation, with one vector per field of `Drone`.
+/// The vectors always have the same length.
+#[derive(Clone, Default, Debug)]
+pub struct Drones {
+    pub id: Vec<u64>,
+    pub pos: Vec<[f64; 3]>,
+    pub prev: Vec<[f64; 3]>,
+    pub vel: Vec<[f64; 2]>,
+    pub born: Vec<u64>,
+    pub steps: Vec<u32>,
+    pub arrived: Vec<bool>,
+    pub kind: Vec<DroneKind>,
+    pub energy: Vec<u32>,
+    pub charging: Vec<bool>,
+    pub trapped: Vec<bool>,
+    pub order: Vec<Option<u32>>,
+} impl Drones {
+    pub fn new() -> Drones {
+        Drones::default()
+    }
+    pub fn len(&self) -> usize {
+        self.id.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.id.is_empty()
+    }
+    pub fn get(&self,  i: usize) -> Drone {
+        Drone {
+            id: self.id[i],
+            pos: self.pos[i],
+            prev: self.prev[i],
+            vel: self.vel[i],
+            born: self.born[i],
+            steps: self.steps[i],
+            arrived: self.arrived[i],
+            kind: self.kind[i],
+            energy: self.energy[i],
+            charging: self.charging[i],
+            trapped: self.trapped[i],
+            order: self.order[i],
+        }
+    }
+    pub fn set(&mut self,  i: usize,  drone: Drone) {
+        self.id[i] = drone.id;
+        self.pos[i] = drone.pos;
+        self.prev[i] = drone.prev;
+        self.vel[i] = drone.vel;
+        self.born[i] = drone.born;
+        self.steps[i] = drone.steps;
+        self.arrived[i] = drone.arrived;
+        self.kind[i] = drone.kind;
+        self.energy[i] = drone.energy;
+        self.charging[i] = drone.charging;
+        self.trapped[i] = drone.trapped;
+        self.order[i] = drone.order;
+    }
+    pub fn push(&mut self,  drone: Drone) {
+        self.id.push(drone.id);
+        self.pos.push(drone.pos);
+        self.prev.push(drone.prev);
+        self.vel.push(drone.vel);
+        self.born.push(drone.born);
+        self.steps.push(drone.steps);
+        self.arrived.push(drone.arrived);
+        self.kind.push(drone.kind);
+        self.energy.push(drone.energy);
+        self.charging.push(drone.charging);
+        self.trapped.push(drone.trapped);
+        self.order.push(drone.order);
+    }
+    pub fn swap(&mut self,  a: usize,  b: usize) {
+        self.id.swap(a, b);
+        self.pos.swap(a, b);
+        self.prev.swap(a, b);
+        self.vel.swap(a, b);
+        self.born.swap(a, b);
+        self.steps.swap(a, b);
+        self.arrived.swap(a, b);
+        self.kind.swap(a, b);
+        self.energy.swap(a, b);
+        self.charging.swap(a, b);
+        self.trapped.swap(a, b);
+        self.order.swap(a, b);
+    }
+    /// Removes drone `i` and moves the last drone to its place.
+    pub fn swap_remove(&mut self,  i: usize) -> Drone {
+        let drone = self.get(i);
+        self.id.swap_remove(i);
+        self.pos.swap_remove(i);
+        self.prev.swap_remove(i);
+        self.vel.swap_remove(i);
+        self.born.swap_remove(i);
+        self.steps.swap_remove(i);
+        self.arrived.swap_remove(i);
+        self.kind.swap_remove(i);
+        self.energy.swap_remove(i);
+        self.charging.swap_remove(i);
+        self.trapped.swap_remove(i);
+        self.order.swap_remove(i);
+        drone
+    }
+    pub fn iter(&self) -> impl Iterator<Item=Drone> + '_ {
+        (0..self.len()).map(move |i| self.get(i) )
+    }
+    pub fn tile(&self,  i: usize) -> [i32; 3] {
+        tile_of(self.pos[i])
+    }
+    /// See `Drone::field()`
+    pub fn field(&self,  i: usize) -> usize {
+        field(self.kind[i], self.charging[i], self.order[i])
+    }
+
+    /// Splits the first `len` drones into chunks and calls `f` with each.
+    pub fn for_each_chunk<F>(&mut self,  len: usize,  f: F)
+    where F: Fn(&mut DronesMut) + Sync + Send {
+        let mut chunks = Vec::with_capacity(len/CHUNK_SIZE + 1);
+        let mut pos = self.pos[..len].chunks_mut(CHUNK_SIZE);
+        let mut prev = self.prev[..len].chunks_mut(CHUNK_SIZE);
+        let mut vel = self.vel[..len].chunks_mut(CHUNK_SIZE);
+        let mut born = self.born[..len].chunks(CHUNK_SIZE);
+        let mut steps = self.steps[..len].chunks_mut(CHUNK_SIZE);
+        let mut kind = self.kind[..len].chunks(CHUNK_SIZE);
+        let mut energy = self.energy[..len].chunks_mut(CHUNK_SIZE);
+        let mut charging = self.charging[..len].chunks_mut(CHUNK_SIZE);
+        let mut order = self.order[..len].chunks(CHUNK_SIZE);
+        for (n, id) in self.id[..len].chunks(CHUNK_SIZE).enumerate() {
+            chunks.push(DronesMut {
+                start: n*CHUNK_SIZE,
+                id,
+                pos: pos.next().unwrap(),
+                prev: prev.next().unwrap(),
+                vel: vel.next().unwrap(),
+                born: born.next().unwrap(),
+                steps: steps.next().unwrap(),
+                kind: kind.next().unwrap(),
+                energy: energy.next().unwrap(),
+                charging: charging.next().unwrap(),
+                order: order.next().unwrap(),
+            });
+        }
+        #[cfg(feature="parallel")]
+        chunks.par_iter_mut().for_each(f);
+        #[cfg(not(feature="parallel"))]
+        chunks.iter_mut().for_each(f);
+    }
+}
+
+impl FromIterator<Drone> for Drones {
+    fn from_iter<I: IntoIterator<Item=Drone>>(iter: I) -> Drones {
+        let mut drones = Drones::new();
+        for drone in iter {
+            drones.push(drone);
+        }
+        drones
+    }
+}
+
+
+/// The fields of a range of drones that moving them changes,
+/// and those it needs to read. Index 0 is drone `start`.
+pub struct DronesMut<'a> {
+    pub start: usize,
+    pub id: &'a [u64],
+    pub pos: &'a mut [[f64; 3]],
+    pub prev: &'a mut [[f64; 3]],
+    pub vel: &'a mut [[f64; 2]],
+    pub born: &'a [u64],
+    pub steps: &'a mut [u32],
+    pub kind: &'a [DroneKind],
+    pub energy: &'a mut [u32],
+    pub charging: &'a mut [bool],
+    pub order: &'a [Option<u32>],
+} impl<'a> DronesMut<'a> {
+    /// See `Drone::field()`
+    pub fn field(&self,  j: usize) -> usize {
+        field(self.kind[j], self.charging[j], self.order[j])
+    }
+    pub fn len(&self) -> usize {
+        self.id.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.id.is_empty()
+    }
+}
@@ -0,0 +1,162 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Things that happen in a simulation, for analysing runs afterwards.
+//!
+//! Register a `Subscriber` with `Simulation::subscribe()` to receive them,
+//! or use `CsvWriter` or `JsonLinesWriter` to write them to a file.
+
+use std::io::{self,Write};
+use board::Tile;
+
+
+#[derive(Clone,Copy, PartialEq, Debug)]
+pub enum Event {
+    /// A drone was created by a spawner, cloned or placed.
+    Spawned{drone: u64,  pos: [i32; 3]},
+    /// A drone entered another tile.
+    Moved{drone: u64,  to: [i32; 3]},
+    /// A drone reached the target for the first time.
+    Arrived{drone: u64,  pos: [i32; 3]},
+    /// A drone was removed because it was inside a wall or a removed floor,
+    /// or by the user.
+    Destroyed{drone: u64,  pos: [i32; 3]},
+    /// A drone can no longer reach where it is going.
+    Trapped{drone: u64,  pos: [i32; 3]},
+    /// A tile was edited, for example a wall placed or removed.
+    TileChanged{pos: [i32; 3],  tile: Tile},
+    /// The target was moved or removed.
+    TargetMoved{to: Option<[i32; 3]>},
+    PathsRecomputed,
+} impl Event {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Event::Spawned{..} => "spawned",
+            Event::Moved{..} => "moved",
+            Event::Arrived{..} => "arrived",
+            Event::Destroyed{..} => "destroyed",
+            Event::Trapped{..} => "trapped",
+            Event::TileChanged{..} => "tile_changed",
+            Event::TargetMoved{..} => "target_moved",
+            Event::PathsRecomputed => "paths_recomputed",
+        }
+    }
+    /// The id of the drone the event is about, if any.
+    pub fn drone(&self) -> Option<u64> {
+        match *self {
+            Event::Spawned{drone, ..} | Event::Moved{drone, ..} | Event::Arrived{drone, ..}
+            | Event::Destroyed{drone, ..} | Event::Trapped{drone, ..} => Some(drone),
+            _ => None,
+        }
+    }
+    /// Where the event happened, or the new position for `Moved` and `TargetMoved`.
+    pub fn pos(&self) -> Option<[i32; 3]> {
+        match *self {
+            Event::Spawned{pos, ..} | Event::Arrived{pos, ..} | Event::Destroyed{pos, ..}
+            | Event::Trapped{pos, ..} | Event::TileChanged{pos, ..} => Some(pos),
+            Event::Moved{to, ..} => Some(to),
+            Event::TargetMoved{to} => to,
+            Event::PathsRecomputed => None,
+        }
+    }
+    pub fn tile(&self) -> Option<Tile> {
+        match *self {
+            Event::TileChanged{tile, ..} => Some(tile),
+            _ => None,
+        }
+    }
+}
+
+
+/// Receives events as they happen.
+pub trait Subscriber {
+    fn event(&mut self,  tick: u64,  event: &Event);
+}
+impl<F: FnMut(u64, &Event)> Subscriber for F {
+    fn event(&mut self,  tick: u64,  event: &Event) {
+        self(tick, event)
+    }
+}
+
+
+/// Writes events as comma-separated values, with a header line.
+/// Fields that don't apply to an event are empty.
+///
+/// Writing stops at the first error, which is printed and kept.
+pub struct CsvWriter<W: Write> {
+    out: Option<W>,
+    error: Option<io::Error>,
+} impl<W: Write> CsvWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        writeln!(out, "tick,event,drone,x,y,floor,tile")?;
+        Ok(CsvWriter{ out: Some(out),  error: None })
+    }
+    /// The error that stopped writing, if any.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+} impl<W: Write> Subscriber for CsvWriter<W> {
+    fn event(&mut self,  tick: u64,  event: &Event) {
+        let drone = event.drone().map_or(String::new(), |id| id.to_string() );
+        let pos = match event.pos() {
+            Some(pos) => format!("{},{},{}", pos[0], pos[1], pos[2]),
+            None => ",,".to_string(),
+        };
+        let tile = event.tile().map_or("", |tile| tile.name() );
+        let line = format!("{},{},{},{},{}", tick, event.name(), drone, pos, tile);
+        if let Some(Err(e)) = self.out.as_mut().map(|out| writeln!(out, "{}", line) ) {
+            eprintln!("stopped writing events: {}", e);
+            self.out = None;
+            self.error = Some(e);
+        }
+    }
+}
+
+
+/// Writes one JSON object per line, without the fields that don't apply.
+///
+/// Writing stops at the first error, which is printed and kept.
+pub struct JsonLinesWriter<W: Write> {
+    out: Option<W>,
+    error: Option<io::Error>,
+} impl<W: Write> JsonLinesWriter<W> {
+    pub fn new(out: W) -> Self {
+        JsonLinesWriter{ out: Some(out),  error: None }
+    }
+    /// The error that stopped writing, if any.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+} impl<W: Write> Subscriber for JsonLinesWriter<W> {
+    fn event(&mut self,  tick: u64,  event: &Event) {
+        let mut line = format!("{{\"tick\":{},\"event\":\"{}\"", tick, event.name());
+        if let Some(drone) = event.drone() {
+            line += &format!(",\"drone\":{}", drone);
+        }
+        if let Some(pos) = event.pos() {
+            line += &format!(",\"pos\":[{},{},{}]", pos[0], pos[1], pos[2]);
+        }
+        if let Some(tile) = event.tile() {
+            line += &format!(",\"tile\":\"{}\"", tile.name());
+        }
+        line += "}";
+        if let Some(Err(e)) = self.out.as_mut().map(|out| writeln!(out, "{}", line) ) {
+            eprintln!("stopped writing events: {}", e);
+            self.out = None;
+            self.error = Some(e);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+//! Where drones go when they cannot reach the target.
+//!
+//! Drones check for a path every tick, so they go back to following it
+//! as soon as their part of the board is connected to the target again.
+
+use rand::Rng;
+use rand::rngs::SmallRng;
+use board::Board;
+use topology::{self,Topology,FlowField,CostModel};
+use kind::DroneKind;
+
+
+/// What drones without a path do.
+#[derive(Clone,Copy, Default, PartialEq,Eq, Debug)]
+pub enum Fallback {
+    /// Move randomly within their tile.
+    #[default]
+    Jitter,
+    /// Stay where they are.
+    Wait,
+    /// Move to a random neighbouring tile.
+    RandomWalk,
+    /// Move to the neighbouring tile that was visited longest ago,
+    /// which spreads drones out to tiles no drone has been to.
+    Explore,
+    /// Go to the tile in their part of the board that is closest to the
+    /// target in a straight line.
+    Closest,
+}
+impl Fallback {
+    pub const ALL: [Fallback; 5] = [
+        Fallback::Jitter, Fallback::Wait, Fallback::RandomWalk, Fallback::Explore, Fallback::Closest
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            Fallback::Jitter => "jitter",
+            Fallback::Wait => "wait",
+            Fallback::RandomWalk => "random_walk",
+            Fallback::Explore => "explore",
+            Fallback::Closest => "closest",
+        }
+    }
+}
+
+
+/// What choosing the next tile needs besides the drone.
+pub struct Fallbacks<'a> {
+    pub fallback: Fallback,
+    /// `Simulation::closest`, one for each of `DroneKind::ALL`
+    pub closest: &'a [FlowField<[i32; 3]>],
+    /// The last tick each tile had a drone in it, indexed by `Topology::index()`.
+    pub visited: &'a [u64],
+} impl<'a> Fallbacks<'a> {
+    /// The tile a drone of `kind` without a path should move to from `tile`,
+    /// or `None` to stay in it.
+    pub fn next_tile(&self,  board: &Board,  kind: DroneKind,  tile: [i32; 3],  rng: &mut SmallRng)
+    -> Option<[i32; 3]> {
+        let mut neighbours = Vec::new();
+        match self.fallback {
+            Fallback::Jitter | Fallback::Wait => return None,
+            Fallback::Closest => {
+                let step = self.closest.get(kind as usize)?.get(board, tile)?;
+                return Some(step.next).filter(|&next| next != tile );
+            }
+            Fallback::RandomWalk | Fallback::Explore => {
+                board.neighbours(tile, |to| {
+                    if kind.cost(board, tile, to).is_some() {
+                        neighbours.push(to);
+                    }
+                });
+            }
+        }
+        if self.fallback == Fallback::Explore {
+            let visited = |p| self.visited.get(board.index(p)).cloned().unwrap_or(0);
+            let oldest = neighbours.iter().map(|&p| visited(p) ).min()?;
+            neighbours.retain(|&p| visited(p) == oldest );
+        }
+        if neighbours.is_empty() {
+            None
+        } else {
+            Some(neighbours[rng.gen_range(0, neighbours.len())])
+        }
+    }
+}
+
+
+/// Finds the way from every tile that `kind` cannot get to `target` from,
+/// to the tile closest to `target` in a straight line that it can get to.
+/// `to_target` is the flow field of `kind` to `target`.
+pub fn closest(board: &Board,  kind: DroneKind,  to_target: &FlowField<[i32; 3]>,  target: [i32; 3])
+-> FlowField<[i32; 3]> {
+    let mut stranded: Vec<[i32; 3]> = (0..board.tiles.len())
+        .map(|i| board.position(i) )
+        .filter(|&p| kind.can_enter(board[p])  &&  to_target.get(board, p).is_none() )
+        .collect();
+    let distance = |p: [i32; 3]| (0..3).map(|i| ((p[i]-target[i]) as i64).pow(2) ).sum::<i64>();
+    stranded.sort_by_key(|&p| (distance(p), board.index(p)) );
+
+    // the first tile of each part of the board is the closest one in it
+    let mut reached = vec![false; board.node_count()];
+    let mut closest = Vec::new();
+    let mut to_check = Vec::new();
+    for p in stranded {
+        if reached[board.index(p)] {
+            continue;
+        }
+        closest.push(p);
+        reached[board.index(p)] = true;
+        to_check.push(p);
+        while let Some(to) = to_check.pop() {
+            board.neighbours(to, |from| {
+                if !reached[board.index(from)]  &&  kind.cost(board, from, to).is_some() {
+                    reached[board.index(from)] = true;
+                    to_check.push(from);
+                }
+            });
+        }
+    }
+    topology::dijkstra(board, &kind, &closest)
+}
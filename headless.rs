@@ -0,0 +1,253 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+//! Runs a scenario without a window for many seeds at once, and prints
+//! statistics about each run and all of them together.
+
+use std::{env,fs,process,thread};
+use std::fs::File;
+use std::io::{Write,BufWriter};
+use std::sync::Mutex;
+extern crate pistonpath;
+use pistonpath::scenario::Scenario;
+use pistonpath::sim::Simulation;
+use pistonpath::stats::PATH_LENGTH_BUCKET;
+#[cfg(feature="scripting")]
+use pistonpath::script::Script;
+
+const USAGE: &str = "usage: headless [--ticks NUMBER] [--seed NUMBER] [--seeds NUMBER] [--threads NUMBER] [--script FILE] [--csv FILE] [SCENARIO_FILE]";
+const DEFAULT_TICKS: u64 = 1000;
+
+
+struct Args {
+    scenario: Scenario,
+    ticks: u64,
+    /// The first seed, the others follow it.
+    seed: u64,
+    seeds: u64,
+    threads: usize,
+    /// Rhai script file
+    script: Option<String>,
+    /// File to write one line per seed to
+    csv: Option<String>,
+}
+
+/// Reads the options and scenario file from the command line, and exits on errors.
+fn parse_args() -> Args {
+    fn number<T: std::str::FromStr>(option: &str,  value: Option<String>) -> T {
+        match value.map(|n| n.parse::<T>() ) {
+            Some(Ok(n)) => n,
+            _ => {
+                eprintln!("{} needs a positive integer", option);
+                process::exit(2);
+            }
+        }
+    }
+    let mut scenario = None;
+    let mut ticks = DEFAULT_TICKS;
+    let mut seed = None;
+    let mut seeds = 1;
+    let mut threads = thread::available_parallelism().map_or(1, |n| n.get() );
+    let mut csv = None;
+    let mut script = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--ticks" => ticks = number(&arg, args.next()),
+            "--seed" => seed = Some(number(&arg, args.next())),
+            "--seeds" => seeds = number(&arg, args.next()),
+            "--threads" => threads = number::<usize>(&arg, args.next()).max(1),
+            "--csv" | "--script" => {
+                let file = args.next().unwrap_or_else(|| {
+                    eprintln!("{} needs a file name", arg);
+                    process::exit(2);
+                });
+                if arg == "--csv" {
+                    csv = Some(file);
+                } else {
+                    script = Some(file);
+                }
+            }
+            _ if arg.starts_with('-')  ||  scenario.is_some() => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+            _ => {
+                let text = fs::read_to_string(&arg).unwrap_or_else(|e| {
+                    eprintln!("cannot read {}: {}", arg, e);
+                    process::exit(1);
+                });
+                scenario = Some(Scenario::parse(&text).unwrap_or_else(|e| {
+                    eprintln!("{}: {}", arg, e);
+                    process::exit(1);
+                }));
+            }
+        }
+    }
+    let scenario: Scenario = scenario.unwrap_or_default();
+    // the command line overrides the scenario file
+    let seed = seed.or(scenario.seed).unwrap_or(0);
+    Args{scenario, ticks, seed, seeds, threads, script, csv}
+}
+
+
+/// What is measured for each run, in addition to the path lengths of arrived drones.
+const METRICS: [&str; 12] = [
+    "drones", "spawned", "placed", "arrived", "destroyed", "relocated", "removed", "trapped",
+    "stranded", "mean_travel_time", "max_travel_time", "throughput",
+];
+type Metrics = ([f64; 12], Vec<u64>);
+
+/// Loads the script, and exits if it cannot be loaded.
+#[cfg(feature="scripting")]
+fn load_script(name: &str) -> Script {
+    Script::load(name.as_ref()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    })
+}
+
+/// Runs the scenario for `ticks` and returns the value of each of `METRICS`
+/// and `Stats::path_lengths`.
+#[cfg(feature="scripting")]
+fn run(scenario: Scenario,  script: Option<&str>,  seed: u64,  ticks: u64) -> Metrics {
+    let mut sim = Simulation::new(scenario, seed);
+    let mut script = script.map(load_script);
+    if let Some(script) = &mut script {
+        if let Err(e) = script.start(&mut sim) {
+            eprintln!("seed {}: {}", seed, e);
+        }
+    }
+    for _ in 0..ticks {
+        sim.tick();
+        if let Some(script) = &mut script {
+            if let Err(e) = script.tick(&mut sim) {
+                eprintln!("seed {}: {}", seed, e);
+            }
+        }
+    }
+    metrics(&sim)
+}
+#[cfg(not(feature="scripting"))]
+fn run(scenario: Scenario,  _: Option<&str>,  seed: u64,  ticks: u64) -> Metrics {
+    let mut sim = Simulation::new(scenario, seed);
+    for _ in 0..ticks {
+        sim.tick();
+    }
+    metrics(&sim)
+}
+
+fn metrics(sim: &Simulation) -> Metrics {
+    let stats = &sim.stats;
+    let metrics = [
+        sim.drones.len() as f64,
+        stats.spawned as f64,
+        stats.placed as f64,
+        stats.arrived as f64,
+        stats.destroyed as f64,
+        stats.relocated as f64,
+        stats.removed as f64,
+        stats.trapped as f64,
+        stats.stranded as f64,
+        stats.mean_travel_time().unwrap_or(f64::NAN),
+        stats.max_travel_time as f64,
+        stats.throughput(sim.ticks),
+    ];
+    (metrics, stats.path_lengths.clone())
+}
+
+fn main() {
+    let args = parse_args();
+    #[cfg(feature="scripting")]
+    if let Some(name) = &args.script {
+        load_script(name); // exit before starting if it doesn't compile
+    }
+    #[cfg(not(feature="scripting"))]
+    if args.script.is_some() {
+        eprintln!("--script needs building with --features scripting");
+        process::exit(2);
+    }
+    let seeds: Vec<u64> = (0..args.seeds).map(|n| args.seed.wrapping_add(n) ).collect();
+
+    // threads take the next seed until there are none left
+    let next = Mutex::new(seeds.iter());
+    let results = Mutex::new(Vec::with_capacity(seeds.len()));
+    thread::scope(|scope| {
+        for _ in 0..args.threads.min(seeds.len()) {
+            scope.spawn(|| loop {
+                let seed = match next.lock().unwrap().next() {
+                    Some(&seed) => seed,
+                    None => break,
+                };
+                let metrics = run(args.scenario.clone(), args.script.as_deref(), seed, args.ticks);
+                results.lock().unwrap().push((seed, metrics));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|&(seed, _)| seed );
+
+    if let Some(name) = &args.csv {
+        let written = File::create(name).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            writeln!(out, "seed,{},path_lengths", METRICS.join(","))?;
+            for (seed, (metrics, path_lengths)) in &results {
+                let values: Vec<String> = metrics.iter().map(|v| v.to_string() ).collect();
+                let path_lengths: Vec<String> = path_lengths.iter().map(|n| n.to_string() ).collect();
+                writeln!(out, "{},{},{}", seed, values.join(","), path_lengths.join(" "))?;
+            }
+            out.flush()
+        });
+        if let Err(e) = written {
+            eprintln!("cannot write to {}: {}", name, e);
+            process::exit(1);
+        }
+    }
+
+    println!("{} ticks with {} seeds from {}", args.ticks, seeds.len(), args.seed);
+    println!("{:>18} {:>12} {:>12} {:>12}", "", "mean", "min", "max");
+    for (i, name) in METRICS.iter().enumerate() {
+        // runs where nothing arrived have no travel time
+        let values: Vec<f64> = results.iter().map(|(_, (metrics, _))| metrics[i] ).filter(|v| !v.is_nan() ).collect();
+        if values.is_empty() {
+            println!("{:>18} {:>12} {:>12} {:>12}", name, "-", "-", "-");
+            continue;
+        }
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        println!("{:>18} {:>12.2} {:>12.2} {:>12.2}", name, mean, min, max);
+    }
+
+    let mut path_lengths = Vec::<u64>::new();
+    for (_, (_, lengths)) in &results {
+        if lengths.len() > path_lengths.len() {
+            path_lengths.resize(lengths.len(), 0);
+        }
+        for (total, &n) in path_lengths.iter_mut().zip(lengths) {
+            *total += n;
+        }
+    }
+    if !path_lengths.is_empty() {
+        println!("{:>18} {:>12}", "path length", "arrived");
+        for (bucket, &n) in path_lengths.iter().enumerate() {
+            let first = bucket as u32 * PATH_LENGTH_BUCKET;
+            let tiles = format!("{}-{} tiles", first, first + PATH_LENGTH_BUCKET - 1);
+            println!("{:>18} {:>12.2}", tiles, n as f64 / results.len() as f64);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+//! Undoing and redoing changes to the board.
+
+use std::collections::HashMap;
+use board::{Board,Tile};
+use board::Tile::*;
+use kind::DroneKind;
+use sim::Simulation;
+
+
+/// A change to the board that can be reversed, found by comparing it before
+/// and after, so that every way of editing can be undone.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Edit {
+    /// How many floors there were before and after.
+    pub floors: (i32, i32),
+    /// Position, tile before and tile after.
+    /// Tiles on a floor that only existed before or after are Open on the other side.
+    pub tiles: Vec<([i32; 3], Tile, Tile)>,
+    /// Position, kind before and kind after, for spawners.
+    pub spawner_kinds: Vec<([i32; 3], Option<DroneKind>, Option<DroneKind>)>,
+} impl Edit {
+    fn between(before: &Board,  before_kinds: &HashMap<[i32; 3], DroneKind>,
+               after: &Board,  after_kinds: &HashMap<[i32; 3], DroneKind>) -> Edit {
+        let tile_at = |board: &Board,  p| board.get(p).unwrap_or(Open);
+        let floors = (before.floors(), after.floors());
+        let top = Board::positions([0, 0, 0], [after.width-1, after.height-1, floors.0.max(floors.1)-1]);
+        let tiles = top.map(|p| (p, tile_at(before, p), tile_at(after, p)) )
+            .filter(|&(_, from, to)| from != to )
+            .collect();
+        let mut spawner_kinds: Vec<_> = before_kinds.keys().chain(after_kinds.keys())
+            .map(|&p| (p, before_kinds.get(&p).cloned(), after_kinds.get(&p).cloned()) )
+            .filter(|&(_, from, to)| from != to )
+            .collect();
+        spawner_kinds.sort_by_key(|&(p, _, _)| (p[2], p[1], p[0]) );
+        spawner_kinds.dedup();
+        Edit{floors, tiles, spawner_kinds}
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.floors.0 == self.floors.1  &&  self.tiles.is_empty()  &&  self.spawner_kinds.is_empty()
+    }
+
+    /// The edit that reverses this one.
+    pub fn reversed(&self) -> Edit {
+        Edit {
+            floors: (self.floors.1, self.floors.0),
+            tiles: self.tiles.iter().map(|&(p, from, to)| (p, to, from) ).collect(),
+            spawner_kinds: self.spawner_kinds.iter().map(|&(p, from, to)| (p, to, from) ).collect(),
+        }
+    }
+
+    /// Makes the tiles what they were after the edit.
+    /// Walls are placed with `build_walls()`, so the wall policy applies.
+    pub fn apply(&self,  sim: &mut Simulation) {
+        while sim.board.floors() < self.floors.1 {
+            sim.add_floor();
+        }
+        while sim.board.floors() > self.floors.1 {
+            sim.remove_floor();
+        }
+        let mut walls = Vec::new();
+        let mut target = None;
+        for &(p, _, to) in &self.tiles {
+            if !sim.board.contains(p) {
+                continue;
+            }
+            if sim.target == Some(p)  &&  to != Target {
+                sim.move_target(None);
+            }
+            match to {
+                Wall => walls.push(p),
+                Target => target = Some(p),
+                tile => sim.set_tile(p, tile),
+            }
+        }
+        sim.build_walls(&walls);
+        if target.is_some() {
+            sim.move_target(target);
+        }
+        for &(p, _, kind) in &self.spawner_kinds {
+            match kind {
+                Some(kind) => sim.spawner_kinds.insert(p, kind),
+                None => sim.spawner_kinds.remove(&p),
+            };
+        }
+        sim.update_paths();
+    }
+}
+
+
+/// Edits that can be undone and redone.
+#[derive(Clone, Default, Debug)]
+pub struct History {
+    /// Oldest first
+    pub undo: Vec<Edit>,
+    /// The next one to redo last
+    pub redo: Vec<Edit>,
+    /// The board before the edit in progress
+    started: Option<(Board, HashMap<[i32; 3], DroneKind>)>,
+} impl History {
+    /// Starts an edit, which lasts until `end()` so that a whole drag becomes
+    /// one step. Does nothing if an edit is already in progress.
+    pub fn begin(&mut self,  sim: &Simulation) {
+        if self.started.is_none() {
+            self.started = Some((sim.board.clone(), sim.spawner_kinds.clone()));
+        }
+    }
+    /// Finishes the edit in progress and returns whether anything changed.
+    pub fn end(&mut self,  sim: &Simulation) -> bool {
+        let edit = match self.started.take() {
+            Some((board, kinds)) => Edit::between(&board, &kinds, &sim.board, &sim.spawner_kinds),
+            None => return false,
+        };
+        if edit.is_empty() {
+            return false;
+        }
+        self.undo.push(edit);
+        self.redo.clear();
+        true
+    }
+
+    /// Reverses the most recent edit, and returns false if there is none.
+    pub fn undo(&mut self,  sim: &mut Simulation) -> bool {
+        self.end(sim);
+        match self.undo.pop() {
+            Some(edit) => {
+                edit.reversed().apply(sim);
+                self.redo.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Repeats the most recently undone edit, and returns false if there is none.
+    pub fn redo(&mut self,  sim: &mut Simulation) -> bool {
+        self.end(sim);
+        match self.redo.pop() {
+            Some(edit) => {
+                edit.apply(sim);
+                self.undo.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+}
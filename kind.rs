@@ -0,0 +1,84 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Drones with different speeds and tiles they can move through.
+
+use board::{Board,Tile};
+use board::Tile::*;
+use topology::CostModel;
+
+
+#[derive(Clone,Copy, Default, PartialEq,Eq,Hash, Debug)]
+pub enum DroneKind {
+    /// Fast, but gets stuck in mud.
+    #[default]
+    Wheeled,
+    /// Slow, and slowed further by mud.
+    Tracked,
+    /// Flies over walls and mud.
+    Flying,
+}
+use self::DroneKind::*;
+impl DroneKind {
+    /// The order is also the index of each kind's flow field.
+    pub const ALL: [DroneKind; 3] = [Wheeled, Tracked, Flying];
+    pub fn name(self) -> &'static str {
+        match self {
+            Wheeled => "wheeled",
+            Tracked => "tracked",
+            Flying => "flying",
+        }
+    }
+    /// Tiles per tick, relative to the default speed.
+    pub fn speed(self) -> f64 {
+        match self {
+            Wheeled => 1.0,
+            Tracked => 0.5,
+            Flying => 0.75,
+        }
+    }
+    /// `speed()` while moving into or through `tile`, which is slower
+    /// on tiles that cost more.
+    pub fn speed_on(self,  tile: Tile) -> f64 {
+        self.speed() / self.tile_cost(tile) as f64
+    }
+    /// The cost of moving into a tile it can enter.
+    fn tile_cost(self,  tile: Tile) -> u32 {
+        if self == Tracked  &&  tile == Mud {2} else {1}
+    }
+    pub fn can_enter(self,  tile: Tile) -> bool {
+        match (self, tile) {
+            (Flying, _) => true,
+            (_, Wall) => false,
+            (Wheeled, Mud) => false,
+            _ => true,
+        }
+    }
+}
+
+/// What a kind of drone can move through, and mud being slow for tracked drones.
+impl CostModel<Board> for DroneKind {
+    fn cost(&self,  board: &Board,  from: [i32; 3],  to: [i32; 3]) -> Option<u32> {
+        if !self.can_enter(board[from])  ||  !self.can_enter(board[to]) {
+            None
+        } else {
+            Some(self.tile_cost(board[to]))
+        }
+    }
+    fn estimate(&self,  _: &Board,  from: [i32; 3],  to: [i32; 3]) -> u32 {
+        (0..3).map(|i| (from[i]-to[i]).unsigned_abs() ).sum()
+    }
+}
@@ -0,0 +1,56 @@
+/* Copyright (C) 2015 Alexandru Cojocaru,
+ *               2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The parts of PistonPath that don't need a window.
+//!
+//! `board` has the tiles drones move on, and `topology` the searches that
+//! compute which way to go. The searches are generic over `Topology` and
+//! `CostModel`, of which `Board` and `UniformCost` are one implementation,
+//! and `kind` has the other cost models, one for each kind of drone.
+//! `sim` moves the drones, starting from a `scenario`, optionally with
+//! the physics in `steering` and sending drones without a path where
+//! `fallback` says, counts what happens in `stats` and reports
+//! it as it happens through `events`. `timeline` keeps snapshots for going
+//! back in time, and `history` keeps edits to the board for undoing them.
+//! `shapes` has the tiles that editing tools change, and `clipboard` copies
+//! and pastes parts of floors.
+//! With the `scripting` feature, `script` runs Rhai scripts on the simulation.
+
+extern crate num;
+extern crate vecmath;
+extern crate rand;
+#[cfg(feature="parallel")]
+extern crate rayon;
+#[cfg(feature="scripting")]
+extern crate rhai;
+
+pub mod board;
+pub mod topology;
+pub mod scenario;
+pub mod kind;
+pub mod drones;
+pub mod sim;
+pub mod steering;
+pub mod fallback;
+pub mod stats;
+pub mod events;
+pub mod timeline;
+pub mod history;
+pub mod shapes;
+pub mod clipboard;
+#[cfg(feature="scripting")]
+pub mod script;
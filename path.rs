@@ -16,7 +16,7 @@
  */
 
 // font-loader's API is too limitied to express "any sans-serif font, ideally monospace"
-const FONT_NAME: &'static str = "arial";
+const FONT_NAME: &str = "arial";
 const DIGIT_ASPECT_RATIO: f64 = 0.71; // observed width/height
 const MAX_DIGITS_SCALE: usize = 2; // don't increase digit size further when distance < 10
 const FONT_RESOLUTION: f64 = 100.0; // glyph height in pixels
@@ -24,24 +24,40 @@ const BORDER_RADIUS: f64 = 0.03; // relative to tile_size
 const TILE_MIN_PADDING: f64 = 0.08;
 const INITIAL_TILE_SIZE: f64 = 50.0;
 
-const BOARD_WIDTH: i32 = 20;
-const BOARD_HEIGHT: i32 = 15;
-const UPDATE_TIME: f64 = 0.20;
+const FLOOR_GAP: f64 = 0.5; // between floors shown side by side, in tiles
+const UPDATE_TIME: f64 = TICK_LENGTH; // at normal speed
+const MIN_SPEED: f64 = 1.0/16.0;
+const MAX_SPEED: f64 = 64.0;
+const MAX_TICKS_PER_UPDATE: u32 = 100; // to catch up after a slow frame
+const MAX_SPEED_BUDGET: f64 = 0.012; // seconds per update spent ticking when running as fast as possible
+const HUD_FONT_SIZE: u32 = 16;
+const HUD_PADDING: f64 = 6.0; // in pixels
+const HISTOGRAM_HEIGHT: f64 = 60.0; // in pixels
+const HISTOGRAM_BAR_WIDTH: f64 = 10.0;
+const BATTERY: u32 = 60; // in tiles, when turned on with b
+const HISTORY_LENGTH: usize = 1000; // snapshots kept for rewinding
+const HISTORY_MAX_DRONES: usize = 2_000_000; // in all snapshots together
+const SCRUBBER_HEIGHT: f64 = 14.0; // in pixels
+const TOOL_BUTTON_SIZE: f64 = 28.0; // in pixels
+const MAX_BRUSH_SIZE: i32 = 9;
+const ZOOM_STEP: f64 = 1.2; // per step of the mouse wheel
+const MIN_TILE_SIZE: f64 = 2.0; // in pixels
+const MAX_TILE_SIZE: f64 = 200.0;
+const MIN_DIGITS_TILE_SIZE: f64 = 12.0; // smaller tiles don't show distances
+const PAN_STEP: f64 = 0.1; // of the window size, per arrow key press
+const MINIMAP_SIZE: f64 = 160.0; // in pixels, of the longest side
+const TOOLTIP_OFFSET: f64 = 16.0; // from the cursor, in pixels
 
 
-use std::ops::Neg;
+use std::ops::Range;
+use std::collections::HashSet;
 use std::cmp;
+use std::{env,fs,mem,process};
+use std::fs::File;
+use std::io::BufWriter;
 use std::time::Instant;
-use std::collections::vec_deque::VecDeque;
 extern crate num;
-use num::{Zero,One,ToPrimitive};
-extern crate vecmath;
-use vecmath::vec2_add; // Vector2 is [T; 2]
-// Why not just use `+`?
-// Any math library in rust have to make a choice: Either use primitive slices or tuples
-// which make constructing and destructuring pain-free, or use std::ops::*.
-// Rusts coherence rules prevents them for doing both:
-// You cannot implement an external trait for a foreign type.
+use num::ToPrimitive;
 extern crate piston_window;
 use piston_window::{Context,DrawState,Transformed,color,math}; // from piston2d-graphics
 use piston_window::types::Color; // from piston2d-graphics
@@ -49,116 +65,479 @@ use piston_window::{MouseButton,Key};// from piston::input
 use piston_window::{Event,Loop,RenderArgs,UpdateArgs,Input,ResizeArgs}; // from piston_input
 use piston_window::{ButtonArgs,ButtonState,Button,Motion}; // from piston_input
 use piston_window::draw_state::Blend; // from piston2d-graphics
-use piston_window::WindowSettings; // from piston::window
+use piston_window::{WindowSettings,AdvancedWindow}; // from piston::window
 use piston_window::Events; // from piston::event_loop
 use piston_window::PistonWindow; // from piston_window
 use piston_window::TextureSettings; // from graphicsz65lw
+use piston_window::character::CharacterCache; // from piston2d-graphics
+use piston_window::{Graphics,BACK_END_MAX_VERTEX_COUNT}; // from piston2d-graphics
+use piston_window::triangulation::{tx,ty}; // from piston2d-graphics
 extern crate opengl_graphics;
-use opengl_graphics::{GlGraphics,GlyphCache,OpenGL};
+use opengl_graphics::{GlGraphics,GlyphCache,OpenGL,Texture};
+use opengl_graphics::{CreateTexture,Format,Filter}; // from piston-texture
 extern crate rand;
-use rand::{Rng,FromEntropy};
-use rand::rngs::SmallRng;
-use rand::distributions::Open01;
 extern crate font_loader;
 use font_loader::system_fonts::{FontProperty,FontPropertyBuilder};
+extern crate pistonpath;
+use pistonpath::board::{Board,Tile,Direction};
+use pistonpath::topology::{self,Topology,CostModel};
+use pistonpath::board::Tile::*; // use Wall instead of Tile::Wall
+use pistonpath::scenario::Scenario;
+use pistonpath::sim::{Simulation,Arrival,WallPolicy,Movement};
+use pistonpath::drones::DRONE_SIZE;
+use pistonpath::stats::{PATH_LENGTH_BUCKET,TICK_LENGTH};
+use pistonpath::kind::DroneKind;
+use pistonpath::fallback::Fallback;
+use pistonpath::events::{CsvWriter,JsonLinesWriter};
+use pistonpath::timeline::Timeline;
+use pistonpath::history::History;
+use pistonpath::shapes;
+use pistonpath::clipboard::Clipboard;
+#[cfg(feature="scripting")]
+use pistonpath::script::Script;
 
 
-#[derive(Clone,Copy, PartialEq,Eq)]
-enum Direction {North, South, East, West}
-use self::Direction::*;
-impl Direction {
-    /// Is generic so it can produce both floats and integers
-    fn unit_vector<T:Zero+One+Neg<Output=T>>(self) -> [T; 2] {
+fn tile_color(tile: Tile) -> Color { match tile {
+    Wall     => color::hex("002951"),
+    Target   => color::hex("8ba673"),
+    Open     => color::hex("001122"),
+    Stairs   => color::hex("4d3b24"),
+    Elevator => color::hex("2f2f5b"),
+    Spawner  => color::hex("5b2f4d"),
+    Mud      => color::hex("3b2a14"),
+    Charger  => color::hex("1f4d2f"),
+}}
+
+/// The fill and border color of drones.
+fn kind_colors(kind: DroneKind) -> (Color, Color) { match kind {
+    DroneKind::Wheeled => (color::hex("ee2222"), color::hex("330000")),
+    DroneKind::Tracked => (color::hex("ee9922"), color::hex("332200")),
+    DroneKind::Flying  => (color::hex("22ccee"), color::hex("002233")),
+}}
+
+
+/// Rectangles of the same color, drawn with one call instead of one each.
+struct RectBatch {
+    color: Color,
+    /// Two triangles per rectangle, already transformed.
+    vertices: Vec<[f32; 2]>,
+} impl RectBatch {
+    fn new(color: Color) -> RectBatch {
+        RectBatch{color,  vertices: Vec::new()}
+    }
+    fn add(&mut self,  [x,y,w,h]: [f64; 4],  transform: math::Matrix2d) {
+        let corner = |x, y| [tx(transform, x, y),  ty(transform, x, y)];
+        let (a, b, c, d) = (corner(x,y), corner(x+w,y), corner(x+w,y+h), corner(x,y+h));
+        self.vertices.extend_from_slice(&[a, b, c,  a, c, d]);
+    }
+    /// Draws and removes the rectangles.
+    fn draw(&mut self,  draw_state: &DrawState,  gfx: &mut GlGraphics) {
+        // the back end can only take so many vertices at a time
+        let chunk = BACK_END_MAX_VERTEX_COUNT - BACK_END_MAX_VERTEX_COUNT%6;
+        let vertices = &self.vertices;
+        gfx.tri_list(draw_state, &self.color, |f| {
+            for part in vertices.chunks(chunk) {
+                f(part);
+            }
+        });
+        self.vertices.clear();
+    }
+}
+
+
+/// What left clicking does, selected with the number keys.
+#[derive(Clone,Copy, PartialEq,Eq, Debug)]
+enum Tool {
+    Wall,
+    Erase,
+    Mud,
+    Stairs,
+    Elevator,
+    Charger,
+    Target,
+    Spawner,
+    Drone,
+    /// Selects the tool that would create the clicked tile.
+    Eyedropper,
+    /// Selects a rectangle to copy or cut.
+    Select,
+    /// Selects drones, and sends them where right clicked.
+    Command,
+}
+impl Tool {
+    /// In the order of the number keys, starting with 1 and ending with 0
+    /// and then ` and g.
+    const ALL: [Tool; 12] = [
+        Tool::Wall, Tool::Erase, Tool::Mud, Tool::Stairs, Tool::Elevator,
+        Tool::Charger, Tool::Target, Tool::Spawner, Tool::Drone, Tool::Eyedropper,
+        Tool::Select, Tool::Command,
+    ];
+    const KEYS: [&'static str; 12] = ["1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "`", "g"];
+    fn name(self) -> &'static str {
         match self {
-            North => [T::zero(),       T::one()      ],
-            South => [T::zero(),       T::one().neg()],
-            East  => [T::one(),        T::zero()     ],
-            West  => [T::one().neg(),  T::zero()     ],
+            Tool::Wall => "wall",
+            Tool::Erase => "erase",
+            Tool::Mud => "mud",
+            Tool::Stairs => "stairs",
+            Tool::Elevator => "elevator",
+            Tool::Charger => "charger",
+            Tool::Target => "target",
+            Tool::Spawner => "spawner",
+            Tool::Drone => "drone",
+            Tool::Eyedropper => "eyedropper",
+            Tool::Select => "select",
+            Tool::Command => "command",
+        }
+    }
+    /// The tile it places, if any.
+    fn tile(self) -> Option<Tile> {
+        match self {
+            Tool::Wall => Some(Wall),
+            Tool::Erase => Some(Open),
+            Tool::Mud => Some(Mud),
+            Tool::Stairs => Some(Stairs),
+            Tool::Elevator => Some(Elevator),
+            Tool::Charger => Some(Charger),
+            Tool::Target => Some(Target),
+            Tool::Spawner => Some(Spawner),
+            Tool::Drone | Tool::Eyedropper | Tool::Select | Tool::Command => None,
         }
     }
 }
 
+/// How dragging with a tool selects tiles.
+#[derive(Clone,Copy, PartialEq,Eq, Debug)]
+enum Shape {
+    /// The tiles under the brush as the mouse moves.
+    Freehand,
+    /// The tiles between where the drag started and ended.
+    Rectangle,
+    /// A straight line from where the drag started to where it ended.
+    Line,
+    /// The edge of a rectangle.
+    HollowRectangle,
+    /// The edge of the ellipse that fits in a rectangle.
+    Ellipse,
+    /// The clicked tile and all tiles of the same type connected to it.
+    Fill,
+}
+impl Shape {
+    const ALL: [Shape; 6] = [
+        Shape::Freehand, Shape::Rectangle, Shape::Line, Shape::HollowRectangle, Shape::Ellipse, Shape::Fill
+    ];
+    fn name(self) -> &'static str {
+        match self {
+            Shape::Freehand => "freehand",
+            Shape::Rectangle => "rectangle",
+            Shape::Line => "line",
+            Shape::HollowRectangle => "hollow rectangle",
+            Shape::Ellipse => "ellipse",
+            Shape::Fill => "fill",
+        }
+    }
+}
 
+
+/// How drones are drawn between ticks
 #[derive(Clone,Copy, PartialEq,Eq)]
-struct Path {
-    distance: i32,
-    next: Direction,
+enum Interpolation {
+    /// Jump directly to the new position.
+    Off,
+    Linear,
+    /// Speed up and slow down
+    Eased,
 }
 
-#[derive(Clone,Copy, PartialEq,Eq)]
-enum Tile {
-    Wall,
-    Target,
-    Open(Option<Path>),
+fn vec2_lerp(from: [f64; 3],  to: [f64; 3],  t: f64) -> [f64; 2] {
+    [from[0] + (to[0]-from[0])*t,  from[1] + (to[1]-from[1])*t]
 }
-use self::Tile::*; // use Wall instead of Tile::Wall
-impl Tile {
-    fn color(&self) -> Color { match *self {
-        Wall    => color::hex("002951"),
-        Target  => color::hex("8ba673"),
-        Open(_) => color::hex("001122"),
-    }}
+
+
+/// How the view (in tiles) is scaled and moved to the window (in pixels).
+/// Fits and letterboxes the view until zoomed or panned.
+struct Camera {
+    window_size: [f64; 2],
+    /// In pixels
+    tile_size: f64,
+    /// Where the top left corner of the view is in the window
+    offset: [f64; 2],
+    /// Whether `tile_size` and `offset` follow the window and view size.
+    fitted: bool,
+} impl Camera {
+    fn new(window_size: [f64; 2]) -> Camera {
+        Camera{window_size,  tile_size: INITIAL_TILE_SIZE,  offset: [0.0, 0.0],  fitted: true}
+    }
+    /// Returns the tile size and offset.
+    fn layout(&self,  view_size: [f64; 2]) -> (f64, [f64; 2]) {
+        if !self.fitted {
+            return (self.tile_size, self.offset);
+        }
+        let tile_size = f64::min(self.window_size[0] / view_size[0],
+                                 self.window_size[1] / view_size[1]);
+        let offset = [(self.window_size[0] - tile_size*view_size[0]) / 2.0,
+                      (self.window_size[1] - tile_size*view_size[1]) / 2.0];
+        (tile_size, offset)
+    }
+    /// Stops following the window size, keeping the current layout.
+    fn unfit(&mut self,  view_size: [f64; 2]) {
+        let (tile_size, offset) = self.layout(view_size);
+        self.tile_size = tile_size;
+        self.offset = offset;
+        self.fitted = false;
+    }
+    /// Scales by `factor` while keeping what's under `cursor` there.
+    fn zoom(&mut self,  factor: f64,  cursor: [f64; 2],  view_size: [f64; 2]) {
+        self.unfit(view_size);
+        let under = self.to_view(cursor, view_size);
+        self.tile_size = (self.tile_size*factor).clamp(MIN_TILE_SIZE, MAX_TILE_SIZE);
+        self.offset = [cursor[0] - under[0]*self.tile_size,  cursor[1] - under[1]*self.tile_size];
+    }
+    /// Moves the view so that `pos` (in tiles) is in the middle of the window.
+    fn center_on(&mut self,  pos: [f64; 2],  view_size: [f64; 2]) {
+        self.unfit(view_size);
+        self.offset = [self.window_size[0]/2.0 - pos[0]*self.tile_size,
+                       self.window_size[1]/2.0 - pos[1]*self.tile_size];
+    }
+    /// Moves the view by `by` pixels.
+    fn pan(&mut self,  by: [f64; 2],  view_size: [f64; 2]) {
+        self.unfit(view_size);
+        self.offset = [self.offset[0]+by[0],  self.offset[1]+by[1]];
+    }
+    /// Converts a position in the window to a position in the view.
+    fn to_view(&self,  [x,y]: [f64; 2],  view_size: [f64; 2]) -> [f64; 2] {
+        let (tile_size, offset) = self.layout(view_size);
+        [(x - offset[0]) / tile_size,  (y - offset[1]) / tile_size]
+    }
 }
 
 
-type Board = [[Tile; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize];
-// Contains the game logic
+// Handles input and rendering, while `Simulation` contains the game logic
 struct Game<'a> {
-    board: Board,
-    drones: Vec<[f64; 2]>,
-    target: Option<[i32; 2]>,
-    mouse_pos: Option<[i32; 2]>,
-    selection_start: Option<[i32; 2]>,
+    sim: Simulation,
+    /// Snapshots for going back in time, see `update()`.
+    timeline: Timeline,
+    /// Edits for undoing and redoing, saved with the map.
+    history: History,
+    /// Where ctrl+s saves the map
+    map_file: String,
+    /// Whether either ctrl key is held down
+    ctrl: bool,
+    /// Runs after every tick, and is reloaded when the file changes.
+    #[cfg(feature="scripting")]
+    script: Option<Script>,
+    mouse_pos: Option<[i32; 3]>,
+    /// Where a rectangle being dragged started
+    selection_start: Option<[i32; 3]>,
+    /// Opposite corners of what the select tool selected, on the same floor
+    selection: Option<([i32; 3], [i32; 3])>,
+    clipboard: Option<Clipboard>,
+    /// Whether left clicking pastes the clipboard
+    pasting: bool,
+    /// The ids of drones selected with the command tool
+    selected: HashSet<u64>,
+    /// In window coordinates
+    cursor: [f64; 2],
+    /// Where on the hovered floor the cursor is, in tiles
+    hovered_point: Option<[f64; 3]>,
+    /// The tile and drone shown in a fixed panel instead of next to the cursor
+    pinned: Option<([i32; 3], Option<u64>)>,
+    /// The region of each tile for a kind of drone, see `region()`
+    regions: Vec<Option<u32>>,
+    /// The tiles and kind `regions` was computed for
+    regions_of: Option<(Vec<Tile>, DroneKind)>,
+    tool: Tool,
+    shape: Shape,
+    /// The width and height of the freehand brush, in tiles
+    brush_size: i32,
+    /// The last position painted while dragging freehand
+    painting: Option<[i32; 3]>,
+    /// Tiles already changed during this drag, to not place many drones
+    /// on the same tile.
+    painted: HashSet<[i32; 3]>,
+    visible_floor: i32,
+    side_by_side: bool,
+    camera: Camera,
+    /// A texture of each floor with one pixel per tile, for the minimap.
+    minimap: Vec<Texture>,
+    /// The tiles `minimap` was created from, to know when to update it.
+    minimap_tiles: Vec<Tile>,
     paused: bool,
+    /// The tick rate relative to `UPDATE_TIME`
+    speed: f64,
+    /// Tick as often as possible, ignoring `speed`.
+    max_speed: bool,
+    /// For showing how fast `max_speed` is.
+    ticks_last_update: u32,
+    interpolation: Interpolation,
+    /// Whose distances are shown, and which new spawners create.
+    kind: DroneKind,
+    show_stats: bool,
+    /// Kept between frames to not allocate every time, see `render_floor()`
+    drone_batches: Vec<RectBatch>,
     time: f64,
     update_time: f64,
-    rng: SmallRng,
     character_cache: GlyphCache<'a>,
 } impl<'a> Game<'a> {
-    fn new(font_data: &[u8]) -> Game {
-        let mut g = Game {
+    fn new(font_data: &[u8],  sim: Simulation,  history: History,  map_file: String,
+           window_size: [f64; 2]) -> Game<'_> {
+        let mut timeline = Timeline::new(HISTORY_LENGTH, HISTORY_MAX_DRONES);
+        timeline.record(&sim);
+        Game {
+            timeline,
+            history,
+            map_file,
+            ctrl: false,
+            #[cfg(feature="scripting")]
+            script: None,
             character_cache: GlyphCache::from_bytes(font_data, (), TextureSettings::new()).unwrap(),
-            rng: SmallRng::from_entropy(),
             time: 0.0,
             update_time: 0.0,
+            ticks_last_update: 0,
+            interpolation: Interpolation::Linear,
+            kind: DroneKind::default(),
+            show_stats: false,
+            drone_batches: Vec::new(),
+            max_speed: false,
+            speed: 1.0,
             paused: false,
+            side_by_side: false,
+            camera: Camera::new(window_size),
+            minimap: Vec::new(),
+            minimap_tiles: Vec::new(),
+            visible_floor: 0,
             selection_start: None,
+            selection: None,
+            clipboard: None,
+            pasting: false,
+            selected: HashSet::new(),
+            cursor: [0.0, 0.0],
+            hovered_point: None,
+            pinned: None,
+            regions: Vec::new(),
+            regions_of: None,
+            tool: Tool::Wall,
+            shape: Shape::Rectangle,
+            brush_size: 1,
+            painting: None,
+            painted: HashSet::new(),
             mouse_pos: None,
-            target: Some([BOARD_WIDTH/2, BOARD_HEIGHT/2]),
-            drones: Vec::with_capacity(4),
-            board: [[Tile::Open(None); BOARD_WIDTH as usize]; BOARD_HEIGHT as usize],
-        };
-        // set target position
-        g.board[BOARD_HEIGHT as usize/2][BOARD_WIDTH as usize/2] = Target;
-        g.update_paths();
-        // put a drone in the center of each corner tile
-        g.drones.push([0.3, 0.3]);
-        g.drones.push([0.3, BOARD_HEIGHT as f64-0.7]);
-        g.drones.push([BOARD_WIDTH as f64-0.7, 0.3]);
-        g.drones.push([BOARD_WIDTH as f64-0.7, BOARD_HEIGHT as f64-0.7]);
-        return g; // Just `g` would do, but to me looks unfinished below `g.something`.
-    }             // like I forgot to write the rest of the function.
-
-    /// In the returned pair, first[0]<=second[0] and first[1]<=second[1],
+            sim,
+        }
+    }
+
+    /// In the returned pair, first[i]<=second[i] for every axis,
     /// now they can be uused in a loop or draw
-    fn order_points(a:[i32; 2], b:[i32; 2]) -> ([i32; 2],[i32; 2]) {
-        ([cmp::min(a[0], b[0]),  cmp::min(a[1], b[1])],
-         [cmp::max(a[0], b[0]),  cmp::max(a[1], b[1])])
+    fn order_points(a:[i32; 3], b:[i32; 3]) -> ([i32; 3],[i32; 3]) {
+        ([cmp::min(a[0], b[0]),  cmp::min(a[1], b[1]),  cmp::min(a[2], b[2])],
+         [cmp::max(a[0], b[0]),  cmp::max(a[1], b[1]),  cmp::max(a[2], b[2])])
+    }
+
+    /// The floors that are drawn, from left to right.
+    fn shown_floors(&self) -> Range<i32> {
+        if self.side_by_side {
+            0..self.sim.board.floors()
+        } else {
+            self.visible_floor..self.visible_floor+1
+        }
+    }
+    /// Horizontal position of a floor in the view, in tiles.
+    fn floor_offset(&self,  floor: i32) -> f64 {
+        (floor - self.shown_floors().start) as f64 * (self.sim.board.width as f64 + FLOOR_GAP)
+    }
+    /// Size of everything that is drawn, in tiles.
+    fn view_size(&self) -> [f64; 2] {
+        let shown = self.shown_floors();
+        let floors = (shown.end - shown.start) as f64;
+        [floors*self.sim.board.width as f64 + (floors-1.0)*FLOOR_GAP,  self.sim.board.height as f64]
+    }
+    /// The columns and rows of a floor that are inside the window.
+    fn visible_tiles(&self,  floor: i32) -> (Range<i32>, Range<i32>) {
+        let view_size = self.view_size();
+        let first = self.camera.to_view([0.0, 0.0], view_size);
+        let last = self.camera.to_view(self.camera.window_size, view_size);
+        let x = self.floor_offset(floor);
+        let columns = (first[0]-x).floor().max(0.0) as i32 .. (last[0]-x).ceil().min(self.sim.board.width as f64) as i32;
+        let rows = first[1].floor().max(0.0) as i32 .. last[1].ceil().min(self.sim.board.height as f64) as i32;
+        (columns, rows)
+    }
+    /// Updates the hovered tile after the cursor or the camera moved.
+    fn hover(&mut self,  cursor: [f64; 2]) {
+        let view = self.camera.to_view(cursor, self.view_size());
+        let pos = self.tile_at(view);
+        self.cursor = cursor;
+        self.hovered_point = pos.map(|p| [view[0] - self.floor_offset(p[2]),  view[1],  p[2] as f64] );
+        self.mouse_move(pos);
+    }
+    /// The id of the drone under the cursor, if any.
+    fn hovered_drone(&self) -> Option<u64> {
+        let point = self.hovered_point?;
+        let drones = &self.sim.drones;
+        (0..drones.len())
+            .find(|&i| {
+                let pos = drones.pos[i];
+                pos[2] == point[2]  &&  (0..2).all(|axis| point[axis] >= pos[axis]  &&  point[axis] < pos[axis]+DRONE_SIZE )
+            })
+            .map(|i| drones.id[i] )
+    }
+    /// Converts a position in the view (in tiles) to the tile under it, if any.
+    fn tile_at(&self,  [x,y]: [f64; 2]) -> Option<[i32; 3]> {
+        if x < 0.0  ||  y < 0.0  ||  y >= self.sim.board.height as f64 {
+            return None;
+        }
+        let shown = self.shown_floors();
+        let floor = (x / (self.sim.board.width as f64 + FLOOR_GAP)) as i32 + shown.start;
+        let x = x - self.floor_offset(floor);
+        // compare floats to avoid rounding at the edges
+        if floor < shown.end  &&  x < self.sim.board.width as f64 {
+            Some([x as i32, y as i32, floor])
+        } else {
+            None // in the gap or to the right
+        }
+    }
+    fn title(&self) -> String {
+        if self.sim.board.floors() == 1 {
+            "PistonPath".to_string()
+        } else if self.side_by_side {
+            format!("PistonPath - {} floors", self.sim.board.floors())
+        } else {
+            format!("PistonPath - floor {} of {}", self.visible_floor+1, self.sim.board.floors())
+        }
     }
 
     fn render(&mut self,  draw_state: DrawState,  transform: math::Matrix2d,  gfx: &mut GlGraphics) {
+        piston_window::clear(color::BLACK, gfx); // comment out and see!
+
+        for z in self.shown_floors() {
+            let (columns, rows) = self.visible_tiles(z);
+            if columns.is_empty()  ||  rows.is_empty() {
+                continue;
+            }
+            let transform = transform.trans(self.floor_offset(z), 0.0);
+            self.render_floor(z, columns, rows, draw_state, transform, gfx);
+        }
+    }
+
+    /// Draws the visible `columns` and `rows` of floor `z`.
+    fn render_floor(&mut self,  z: i32,  columns: Range<i32>,  rows: Range<i32>,
+                    draw_state: DrawState,  transform: math::Matrix2d,  gfx: &mut GlGraphics) {
         fn to_f64_4<T: ToPrimitive>(a:T, b:T, c:T, d:T) -> [f64; 4] {
             [a.to_f64().unwrap(), b.to_f64().unwrap(), c.to_f64().unwrap(), d.to_f64().unwrap()]
         }
 
-        piston_window::clear(color::BLACK, gfx); // comment out and see!
-
+        let show_digits = self.camera.layout(self.view_size()).0 >= MIN_DIGITS_TILE_SIZE;
         // tiles
-        for (y_usize,ref row) in self.board.iter().enumerate() {
-            for (x_usize,tile) in row.iter().enumerate() {
-                let (x,y) = (x_usize as f64, y_usize as f64);
-                piston_window::rectangle(tile.color(), [x,y,1.0,1.0], transform, gfx);
-                if let Open(Some(path)) = *tile {
+        for y in rows.clone() {
+            for x in columns.clone() {
+                let tile = self.sim.board[[x,y,z]];
+                let path = self.sim.paths(self.kind).get(&self.sim.board, [x,y,z]);
+                let spawner_kind = self.sim.spawner_kinds.get(&[x,y,z]).cloned().unwrap_or_default();
+                let (x,y) = (x as f64, y as f64);
+                piston_window::rectangle(tile_color(tile), [x,y,1.0,1.0], transform, gfx);
+                if tile == Spawner {// what it creates
+                    let marker = [x+1.0-2.0*TILE_MIN_PADDING,  y+TILE_MIN_PADDING,  TILE_MIN_PADDING,  TILE_MIN_PADDING];
+                    piston_window::rectangle(kind_colors(spawner_kind).0, marker, transform, gfx);
+                }
+                if let (Some(path), false, true) = (path, tile == Target, show_digits) {
                     // number rendering
                     let as_str: &str = &path.distance.to_string()[..];
                     let digits = as_str.len(); // digits aren't unicode
@@ -182,197 +561,1185 @@ struct Game<'a> {
                     let char_pos = transform
                         .trans(x + left_padding,  1.0 + y - bottom_padding)
                         .scale(scale_factor, scale_factor);
-                    piston_window::text::Text::new_color(Target.color(), FONT_RESOLUTION as u32)
+                    piston_window::text::Text::new_color(tile_color(Target), FONT_RESOLUTION as u32)
                         .draw(as_str, &mut self.character_cache, &draw_state, char_pos, gfx)
                         .unwrap();
                 }
             }
         }
 
-        // drones
-        for p in &self.drones {
-            let red = color::hex("ee2222");
-            let brown = color::hex("330000");
-            let border = [p[0],p[1],0.4,0.4];
-            let main = [p[0]+0.05,p[1]+0.05,0.3,0.3];
-            piston_window::rectangle(brown, border, transform, gfx);
-            piston_window::rectangle(red, main, transform, gfx);
+        // drones, batched by color as there can be very many
+        if self.drone_batches.is_empty() {
+            for &kind in &DroneKind::ALL {
+                let (fill, outline) = kind_colors(kind);
+                self.drone_batches.push(RectBatch::new(outline));
+                self.drone_batches.push(RectBatch::new(fill));
+            }
+            self.drone_batches.push(RectBatch::new(color::hex("22ee22"))); // battery
+            self.drone_batches.push(RectBatch::new(color::hex("eeee22"))); // charging
+        }
+        let battery = 2*DroneKind::ALL.len();
+        let progress = self.tick_progress();
+        let drones = &self.sim.drones;
+        let visible = |tile: [i32; 3]| {
+            tile[2] == z  &&  tile[0] >= columns.start-1  &&  tile[0] <= columns.end
+            &&  tile[1] >= rows.start-1  &&  tile[1] <= rows.end
+        };
+        let mut halos = RectBatch::new(color::WHITE);
+        for i in (0..drones.len()).filter(|&i| visible(drones.tile(i)) ) {
+            let (prev, pos) = (drones.prev[i], drones.pos[i]);
+            let p = if prev[2] == pos[2] {
+                vec2_lerp(prev, pos, progress)
+            } else {
+                [pos[0], pos[1]] // don't slide across floors
+            };
+            let kind = drones.kind[i] as usize;
+            let border = [p[0],p[1],DRONE_SIZE,DRONE_SIZE];
+            let main = [p[0]+0.05,p[1]+0.05,DRONE_SIZE-0.1,DRONE_SIZE-0.1];
+            if self.selected.contains(&drones.id[i]) {
+                halos.add([p[0]-0.08, p[1]-0.08, DRONE_SIZE+0.16, DRONE_SIZE+0.16], transform);
+            }
+            self.drone_batches[2*kind].add(border, transform);
+            self.drone_batches[2*kind+1].add(main, transform);
+            if let Some(capacity) = self.sim.settings.battery {
+                let charge = drones.energy[i].min(capacity) as f64 / capacity.max(1) as f64;
+                let bar = [p[0],  p[1]+DRONE_SIZE,  DRONE_SIZE*charge,  0.05];
+                self.drone_batches[battery + drones.charging[i] as usize].add(bar, transform);
+            }
         }
+        halos.draw(&draw_state, gfx);
+        for batch in &mut self.drone_batches {
+            batch.draw(&draw_state, gfx);
+        }
+
+        // where the selected drones are going
+        let mut route = RectBatch::new([1.0, 1.0, 1.0, 0.5]);
+        for p in self.routes().into_iter().filter(|p| p[2] == z ) {
+            route.add([p[0] as f64 + 0.4,  p[1] as f64 + 0.4,  0.2,  0.2], transform);
+        }
+        route.draw(&draw_state, gfx);
 
-        // hover highlight and selection
+        if let Some((a, b)) = self.selection.filter(|&(a, _)| a[2] == z ) {
+            let (first, second) = Game::order_points(a, b);
+            let rect = to_f64_4(first[0], first[1],  second[0]-first[0]+1, second[1]-first[1]+1);
+            piston_window::Rectangle::new_border(color::WHITE, 2.0*BORDER_RADIUS)
+                .draw(rect, &draw_state, transform, gfx);
+        }
+
+        // hover highlight and preview of the shape being drawn
         if let Some(mouse_pos) = self.mouse_pos {
-            // selection
-            if let Some(start) = self.selection_start {
-                let (a,b) = Game::order_points(start, mouse_pos);
-                let rect = to_f64_4(a[0], a[1],  b[0]-a[0]+1, b[1]-a[1]+1);
-                let selection_color = [1.0, 1.0, 1.0, 0.2]; // white
-                piston_window::rectangle(selection_color, rect, transform, gfx);
+            // a rectangle can span several floors
+            let single_tile = matches!(self.tool, Tool::Target | Tool::Eyedropper);
+            let preview = match (self.selection_start, self.shape) {
+                (Some(start), _) => self.shape_tiles(start, mouse_pos),
+                (None, Shape::Fill) if !single_tile => self.shape_tiles(mouse_pos, mouse_pos),
+                _ => Vec::new(),
+            };
+            let mut color = match self.tool.tile() {
+                Some(tile) if !single_tile => tile_color(tile),
+                _ => color::WHITE,
+            };
+            color[3] = 0.5;
+            let mut batch = RectBatch::new(color);
+            for p in preview.into_iter().filter(|&p| p[2] == z  &&  self.sim.board.contains(p) ) {
+                batch.add(to_f64_4(p[0], p[1], 1, 1), transform);
+            }
+            batch.draw(&draw_state, gfx);
+            // what would be pasted
+            if let (true, Some(clipboard), true) = (self.pasting, &self.clipboard, mouse_pos[2] == z) {
+                for &tile in &Tile::ALL {
+                    let mut color = tile_color(tile);
+                    color[3] = 0.6;
+                    let mut ghost = RectBatch::new(color);
+                    for (p, _, _) in clipboard.placed(mouse_pos).filter(|&(p, t, _)| t == tile  &&  self.sim.board.contains(p) ) {
+                        ghost.add(to_f64_4(p[0], p[1], 1, 1), transform);
+                    }
+                    ghost.draw(&draw_state, gfx);
+                }
+            }
+            // hover, showing what the brush covers
+            if mouse_pos[2] == z {
+                let mouse_color = [0.9, 1.0, 0.9, 0.1]; // light green
+                let size = match (self.shape, single_tile) {
+                    (Shape::Freehand, false) => self.brush_size,
+                    _ => 1,
+                };
+                let corner = shapes::brush(mouse_pos, size)[0];
+                piston_window::rectangle(mouse_color,  to_f64_4(corner[0], corner[1], size, size),  transform,  gfx);
             }
-            // hover
-            let mouse_color = [0.9, 1.0, 0.9, 0.1]; // light green
-            piston_window::rectangle(mouse_color,  to_f64_4(mouse_pos[0], mouse_pos[1], 1, 1),  transform,  gfx);
         }
 
         // border lines
         let line_color = [0.4, 0.4, 0.4, 0.3]; // grey
-        for y in 1..BOARD_HEIGHT {
-            piston_window::line(line_color, BORDER_RADIUS, to_f64_4(0,y,BOARD_WIDTH,y),  transform, gfx);
+        for y in cmp::max(rows.start, 1)..rows.end {
+            piston_window::line(line_color, BORDER_RADIUS, to_f64_4(columns.start,y,columns.end,y),  transform, gfx);
         }
-        for x in 1..BOARD_WIDTH {
-            piston_window::line(line_color, BORDER_RADIUS, to_f64_4(x,0,x,BOARD_HEIGHT),  transform, gfx);
+        for x in cmp::max(columns.start, 1)..columns.end {
+            piston_window::line(line_color, BORDER_RADIUS, to_f64_4(x,rows.start,x,rows.end),  transform, gfx);
         }
     }
 
     fn update(&mut self, dt: f64) {
+        self.reload_script();
         if self.paused {
             return;
         }
-        self.update_time += dt;
-        if self.update_time-self.time < UPDATE_TIME {
+        self.ticks_last_update = 0;
+        let mut ticked = false;
+        if self.max_speed {
+            let started = Instant::now();
+            while started.elapsed().as_secs_f64() < MAX_SPEED_BUDGET {
+                ticked |= self.step();
+                self.ticks_last_update += 1;
+            }
+        } else {
+            self.update_time += dt;
+            let tick_time = UPDATE_TIME / self.speed;
+            while self.update_time-self.time >= tick_time {
+                self.time += tick_time;
+                ticked |= self.step();
+                self.ticks_last_update += 1;
+                if self.ticks_last_update == MAX_TICKS_PER_UPDATE {
+                    self.time = self.update_time; // give up catching up
+                }
+            }
+        }
+        // while replaying there is nothing new to record
+        if ticked  &&  !self.timeline.is_rewound() {
+            self.timeline.record(&self.sim);
+        }
+    }
+
+    /// Replays the next snapshot after rewinding, or ticks.
+    /// Returns true if it ticked.
+    fn step(&mut self) -> bool {
+        if self.timeline.forward(&mut self.sim) {
+            false
+        } else {
+            self.sim.tick();
+            self.run_script();
+            true
+        }
+    }
+
+    #[cfg(feature="scripting")]
+    fn run_script(&mut self) {
+        if let Some(script) = &mut self.script {
+            if let Err(e) = script.tick(&mut self.sim) {
+                eprintln!("{}", e);
+            }
+        }
+    }
+    #[cfg(not(feature="scripting"))]
+    fn run_script(&mut self) {}
+
+    #[cfg(feature="scripting")]
+    fn reload_script(&mut self) {
+        if let Some(script) = &mut self.script {
+            match script.reload_if_changed(&mut self.sim) {
+                Ok(true) => println!("reloaded the script"),
+                Ok(false) => {}
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+    #[cfg(not(feature="scripting"))]
+    fn reload_script(&mut self) {}
+
+    /// Goes to the snapshot at `fraction` of the timeline, and pauses.
+    fn seek(&mut self,  fraction: f64) {
+        if !self.timeline.is_empty() {
+            let last = self.timeline.len()-1;
+            let index = (fraction.clamp(0.0, 1.0) * last as f64).round() as usize;
+            self.timeline.seek(&mut self.sim, index);
+            self.paused = true;
+            self.time = self.update_time - UPDATE_TIME/self.speed; // don't interpolate
+        }
+    }
+
+    /// How far drones have come from their previous position to the current,
+    /// between 0 and 1.
+    fn tick_progress(&self) -> f64 {
+        if self.max_speed  ||  self.interpolation == Interpolation::Off {
+            return 1.0;
+        }
+        let t = f64::min((self.update_time-self.time) / (UPDATE_TIME/self.speed), 1.0);
+        match self.interpolation {
+            Interpolation::Eased => t*t*(3.0-2.0*t), // smoothstep
+            _ => t,
+        }
+    }
+
+    /// Draws the speed and warnings in the top left corner, in window coordinates,
+    /// and returns the height.
+    fn render_hud(&mut self,  draw_state: DrawState,  transform: math::Matrix2d,  gfx: &mut GlGraphics) -> f64 {
+        let speed = if self.max_speed {
+            format!("max speed ({} ticks per frame)", self.ticks_last_update)
+        } else if self.speed < 1.0 {
+            format!("speed 1/{}", 1.0/self.speed)
+        } else {
+            format!("speed {}", self.speed)
+        };
+        let paused = if self.paused {", paused"} else {""};
+        let mut lines = vec![
+            (format!("{}{}, tick {}", speed, paused, self.sim.ticks), color::WHITE),
+            (format!("{} drones, on arrival: {}, {} movement",
+                     self.sim.drones.len(),
+                     self.sim.settings.on_arrival.name(),
+                     self.sim.settings.movement.name()),
+             color::WHITE),
+            (format!("walls on drones: {} ({} destroyed, {} relocated), without a path: {}",
+                     self.sim.settings.on_wall.name(),
+                     self.sim.stats.destroyed,
+                     self.sim.stats.relocated,
+                     self.sim.settings.fallback.name()),
+             color::WHITE),
+            (format!("{} drones: distances and new spawners", self.kind.name()), color::WHITE),
+            (match self.sim.settings.battery {
+                Some(capacity) => format!("battery: {} tiles, {} out of energy", capacity, self.sim.stats.stranded),
+                None => "battery: unlimited".to_string(),
+             }, color::WHITE),
+        ];
+        if let (true, Some((first, last))) = (self.paused || self.timeline.is_rewound(), self.timeline.range()) {
+            let history = format!("history: ticks {} to {}, , and . to step", first, last);
+            lines.push((history, color::WHITE));
+        }
+        if self.sim.at_max_drones() {
+            let warning = format!("limit of {} drones reached", self.sim.settings.max_drones);
+            lines.push((warning, color::hex("ee2222")));
+        }
+        let width = self.lines_width(&lines);
+        self.render_lines(lines, width, draw_state, transform, gfx)
+    }
+
+    /// Which part of the board `tile` is in for the shown kind of drones.
+    /// Is `None` if it cannot enter the tile.
+    fn region(&mut self,  tile: [i32; 3]) -> Option<u32> {
+        let board = &self.sim.board;
+        let current = self.regions_of.as_ref()
+            .is_some_and(|(tiles, kind)| *kind == self.kind  &&  *tiles == board.tiles );
+        if !current {
+            let kind = self.kind;
+            let enterable = (0..board.tiles.len())
+                .map(|i| board.position(i) )
+                .filter(|&p| kind.can_enter(board[p]) );
+            self.regions = topology::regions(board, &kind, enterable);
+            self.regions_of = Some((board.tiles.clone(), kind));
+        }
+        self.regions[board.index(tile)]
+    }
+
+    /// Describes a tile, and a drone if it still exists.
+    fn inspect(&mut self,  tile: [i32; 3],  drone: Option<u64>) -> Vec<(String, Color)> {
+        if !self.sim.board.contains(tile) {
+            return Vec::new();
+        }
+        let region = self.region(tile);
+        let (sim, kind) = (&self.sim, self.kind);
+        let mut lines = vec![
+            (format!("x {}, y {}, floor {}: {}", tile[0], tile[1], tile[2], sim.board[tile].name()), color::WHITE),
+        ];
+        lines.push((match sim.paths(kind).get(&sim.board, tile) {
+            Some(step) if step.next == tile => "at the target".to_string(),
+            Some(step) => format!("distance {}, next {}", step.distance,
+                                  Direction::between(tile, step.next).map_or("?", Direction::name)),
+            None => "cannot reach the target".to_string(),
+        }, color::WHITE));
+        // the cost only depends on the tile moved to
+        lines.push((match (kind.cost(&sim.board, tile, tile), region) {
+            (Some(cost), Some(region)) => format!("{} drones: cost {}, region {}", kind.name(), cost, region),
+            _ => format!("{} drones cannot enter", kind.name()),
+        }, color::WHITE));
+        let here = (0..sim.drones.len()).filter(|&i| sim.drones.tile(i) == tile ).count();
+        lines.push((format!("{} drones here", here), color::WHITE));
+
+        let drones = &sim.drones;
+        if let Some(i) = drone.and_then(|id| drones.id.iter().position(|&other| other == id ) ) {
+            let d = drones.get(i);
+            lines.push((format!("drone {}, {}", d.id, d.kind.name()), kind_colors(d.kind).0));
+            lines.push((format!("age {} ticks, {} steps", sim.ticks - d.born, d.steps), color::WHITE));
+            lines.push((match d.order {
+                Some(order) => {
+                    let to = sim.destination(order);
+                    format!("sent to x {}, y {}, floor {}", to[0], to[1], to[2])
+                }
+                None if d.arrived => "arrived".to_string(),
+                None if d.trapped => "trapped".to_string(),
+                None => "going to the target".to_string(),
+            }, color::WHITE));
+            if let Some(capacity) = sim.settings.battery {
+                let charging = if d.charging {", charging"} else {""};
+                lines.push((format!("energy {} of {}{}", d.energy.min(capacity), capacity, charging), color::WHITE));
+            }
+            if sim.settings.movement == Movement::Steering {
+                lines.push((format!("velocity {:.2}, {:.2}", d.vel[0], d.vel[1]), color::WHITE));
+            }
+        }
+        lines
+    }
+
+    /// Draws what `inspect()` says about the pinned tile below the HUD,
+    /// or about the hovered one next to the cursor.
+    fn render_inspector(&mut self,  hud_height: f64,  draw_state: DrawState,  transform: math::Matrix2d,
+                        gfx: &mut GlGraphics) {
+        let (lines, corner) = match (self.pinned, self.mouse_pos) {
+            (Some((tile, drone)), _) => (self.inspect(tile, drone), [0.0, hud_height + HUD_PADDING]),
+            (None, Some(tile)) if self.selection_start.is_none()  &&  self.painting.is_none() => {
+                let drone = self.hovered_drone();
+                let corner = [self.cursor[0] + TOOLTIP_OFFSET,  self.cursor[1] + TOOLTIP_OFFSET];
+                (self.inspect(tile, drone), corner)
+            }
+            _ => return,
+        };
+        if lines.is_empty() {
             return;
         }
-        self.time = self.update_time;
-
-        // This is a (probably premature) optimization to reuse self.drones
-        // and avoid allocating and freing every time.
-        // The functional approach would be to iterate, map into a vector with
-        // lengt 0, 1 or 2, flat_map() and then collect().
-        let mut i = 0;
-        let mut len = self.drones.len();// Don't increase when I add new
-        while i < len {
-            let m = self.drones[i];
-            match self.board[m[1] as usize][m[0] as usize] {
-                Open(Some(path)) => {// move along
-                    self.drones[i] = vec2_add(m, path.next.unit_vector());
-                },
-                Open(None) => {// jitter randomly
-                    let min = [(m[0] as i32)as f64, (m[1] as i32)as f64];
-                    let max = vec2_add(min, [0.6,0.6]);
-                    let x = m[0] + self.rng.sample::<f64,_>(Open01) - 0.5;
-                    let y = m[1] + self.rng.sample::<f64,_>(Open01) - 0.5;
-                    if x >= min[0]  &&  x <= max[0] {
-                        self.drones[i][0] = x;
-                    }
-                    if y >= min[1]  &&  y <= max[1] {
-                        self.drones[i][1] = y;
-                    }
-                },
-                Wall => {// remove
-                    let last = self.drones.pop().unwrap();
-                    if i != len-1 {
-                        self.drones[i] = last;
-                    }
-                    len -= 1;
-                    i = i.wrapping_sub(1);
-                },
-                Target if len < 200 => self.drones.push(m),// clone
-                Target => {/*else it gets slow quickly*/},
-            }
-            i = i.wrapping_add(1);
-        }
-    }
-
-    /// Recalculates the numbers when the destination you change the destination.
-    fn update_paths(&mut self) {
-        // reset all
-        for tile in self.board.iter_mut().flat_map(|row| row.iter_mut() ) {
-            if let Open(Some(_)) = *tile {
-                *tile = Open(None);
-            }
-        }
-
-        if let Some(target) = self.target {
-            fn go(board: &mut Board,  p: [i32; 2],  from_dist: i32,  from_dir: Direction) -> bool {
-                if p[0]>=0  &&  p[0]<BOARD_WIDTH
-                && p[1]>=0  &&  p[1]<BOARD_HEIGHT {
-                    let tile = &mut board[p[1]as usize][p[0]as usize];
-                    if let Open(to_path) = *tile {
-                        let default_path = Path{distance: std::i32::MAX,  next: North};
-                        if from_dist < to_path.unwrap_or(default_path).distance {
-                            *tile = Open(Some(Path{distance: from_dist,  next: from_dir}));
-                            true
-                        } else {false}
-                    } else if from_dist==0 && *tile == Target {
-                        true // initial tile
-                    } else {false}
-                } else {false}
-            }
-
-            let mut to_check : VecDeque<([i32; 2], i32, Direction)> = VecDeque::new();
-            to_check.push_back((target, 0, South));
-            while let Some((from_pos, from_dist, from_dir)) = to_check.pop_front() {
-                if go(&mut self.board,  from_pos,  from_dist, from_dir) {
-                    to_check.push_back((vec2_add(from_pos, North.unit_vector()), from_dist+1, South));
-                    to_check.push_back((vec2_add(from_pos, South.unit_vector()), from_dist+1, North));
-                    to_check.push_back((vec2_add(from_pos, West.unit_vector()), from_dist+1,  East));
-                    to_check.push_back((vec2_add(from_pos, East.unit_vector()), from_dist+1,  West));
+        let width = self.lines_width(&lines);
+        // keep tooltips inside the window
+        let size = [width + 2.0*HUD_PADDING,  lines.len() as f64 * (HUD_FONT_SIZE as f64 + HUD_PADDING) + HUD_PADDING];
+        let window_size = self.camera.window_size;
+        let corner = [
+            if corner[0]+size[0] > window_size[0] {self.cursor[0] - TOOLTIP_OFFSET - size[0]} else {corner[0]},
+            if corner[1]+size[1] > window_size[1] {self.cursor[1] - TOOLTIP_OFFSET - size[1]} else {corner[1]},
+        ];
+        self.render_lines(lines, width, draw_state, transform.trans(corner[0], corner[1]), gfx);
+    }
+
+    /// Draws the statistics and a histogram of path lengths in the top right corner.
+    fn render_stats(&mut self,  window_width: f64,  draw_state: DrawState,  transform: math::Matrix2d,  gfx: &mut GlGraphics) {
+        let stats = &self.sim.stats;
+        let mean = match stats.mean_travel_time() {
+            Some(mean) => format!("{:.1}", mean),
+            None => "-".to_string(),
+        };
+        let lines = vec![
+            (format!("spawned: {}, placed: {}", stats.spawned, stats.placed), color::WHITE),
+            (format!("arrived: {}", stats.arrived), color::WHITE),
+            (format!("destroyed: {}, relocated: {}, removed: {}", stats.destroyed, stats.relocated, stats.removed), color::WHITE),
+            (format!("trapped: {}", stats.trapped), color::WHITE),
+            (format!("travel time: mean {}, max {} ticks", mean, stats.max_travel_time), color::WHITE),
+            (format!("throughput: {:.1} per second", stats.throughput(self.sim.ticks)), color::WHITE),
+            (format!("path lengths ({} tiles per bar):", PATH_LENGTH_BUCKET), color::WHITE),
+        ];
+        let bars = self.sim.stats.path_lengths.clone();
+        let width = self.lines_width(&lines).max(bars.len() as f64 * HISTOGRAM_BAR_WIDTH);
+        let x = window_width - width - 2.0*HUD_PADDING;
+        let transform = transform.trans(x, 0.0);
+        let height = self.render_lines(lines, width, draw_state, transform, gfx);
+
+        let background = [0.0, 0.0, 0.0, 0.6];
+        let area = [0.0, height, width + 2.0*HUD_PADDING, HISTOGRAM_HEIGHT + HUD_PADDING];
+        piston_window::rectangle(background, area, transform, gfx);
+        let highest = bars.iter().cloned().max().unwrap_or(0).max(1) as f64;
+        let bottom = height + HISTOGRAM_HEIGHT;
+        for (i, &count) in bars.iter().enumerate() {
+            let bar_height = HISTOGRAM_HEIGHT * count as f64 / highest;
+            let bar = [HUD_PADDING + i as f64 * HISTOGRAM_BAR_WIDTH,  bottom - bar_height,
+                       HISTOGRAM_BAR_WIDTH - 1.0,  bar_height];
+            piston_window::rectangle(color::WHITE, bar, transform, gfx);
+        }
+    }
+
+    /// Selects the tool under `cursor` if it's on the toolbar, and returns whether it was.
+    fn toolbar_click(&mut self,  cursor: [f64; 2],  window_size: [f64; 2]) -> bool {
+        let y = window_size[1] - SCRUBBER_HEIGHT - TOOL_BUTTON_SIZE - HUD_PADDING;
+        let i = (cursor[0] / TOOL_BUTTON_SIZE) as usize;
+        if cursor[1] < y  ||  cursor[1] >= y+TOOL_BUTTON_SIZE  ||  i >= Tool::ALL.len() {
+            return false;
+        }
+        self.tool = Tool::ALL[i];
+        true
+    }
+
+    /// Draws a button for each tool in the bottom left corner, with the
+    /// current one outlined, followed by the shape and brush size.
+    fn render_toolbar(&mut self,  window_size: [f64; 2],  draw_state: DrawState,  transform: math::Matrix2d,
+                      gfx: &mut GlGraphics) {
+        let y = window_size[1] - SCRUBBER_HEIGHT - TOOL_BUTTON_SIZE - HUD_PADDING;
+        let label = format!("{}, {}, brush {}", self.tool.name(), self.shape.name(), self.brush_size);
+        let width = Tool::ALL.len() as f64 * TOOL_BUTTON_SIZE
+                  + self.character_cache.width(HUD_FONT_SIZE, &label).unwrap() + 2.0*HUD_PADDING;
+        let background = [0.0, 0.0, 0.0, 0.6];
+        piston_window::rectangle(background, [0.0, y, width, TOOL_BUTTON_SIZE], transform, gfx);
+        for (i, &tool) in Tool::ALL.iter().enumerate() {
+            let x = i as f64 * TOOL_BUTTON_SIZE;
+            let inner = [x+3.0, y+3.0, TOOL_BUTTON_SIZE-6.0, TOOL_BUTTON_SIZE-6.0];
+            match tool.tile() {
+                Some(tile) => piston_window::rectangle(tile_color(tile), inner, transform, gfx),
+                None if tool == Tool::Drone => {
+                    let (fill, outline) = kind_colors(self.kind);
+                    let size = TOOL_BUTTON_SIZE/2.0;
+                    let drone = [x + size/2.0,  y + size/2.0,  size,  size];
+                    piston_window::rectangle(outline, drone, transform, gfx);
+                    piston_window::rectangle(fill, [drone[0]+2.0, drone[1]+2.0, size-4.0, size-4.0], transform, gfx);
                 }
+                None => {},
             }
+            piston_window::text::Text::new_color(color::WHITE, HUD_FONT_SIZE*3/4)
+                .draw(Tool::KEYS[i], &mut self.character_cache, &draw_state,
+                      transform.trans(x+4.0, y+TOOL_BUTTON_SIZE-5.0), gfx)
+                .unwrap();
+            if tool == self.tool {
+                piston_window::Rectangle::new_border(color::WHITE, 1.0)
+                    .draw([x+1.0, y+1.0, TOOL_BUTTON_SIZE-2.0, TOOL_BUTTON_SIZE-2.0], &draw_state, transform, gfx);
+            }
+        }
+        let x = Tool::ALL.len() as f64 * TOOL_BUTTON_SIZE + HUD_PADDING;
+        piston_window::text::Text::new_color(color::WHITE, HUD_FONT_SIZE)
+            .draw(&label, &mut self.character_cache, &draw_state,
+                  transform.trans(x, y + (TOOL_BUTTON_SIZE+HUD_FONT_SIZE as f64)/2.0 - 2.0), gfx)
+            .unwrap();
+    }
+
+    fn show_scrubber(&self) -> bool {
+        self.timeline.len() > 1  &&  (self.paused  ||  self.timeline.is_rewound())
+    }
+
+    /// Draws a bar along the bottom of the window for the recorded ticks,
+    /// filled up to the current one.
+    fn render_scrubber(&self,  window_size: [f64; 2],  transform: math::Matrix2d,  gfx: &mut GlGraphics) {
+        let y = window_size[1] - SCRUBBER_HEIGHT;
+        let background = [0.0, 0.0, 0.0, 0.6];
+        piston_window::rectangle(background, [0.0, y, window_size[0], SCRUBBER_HEIGHT], transform, gfx);
+        let fraction = self.timeline.position() as f64 / (self.timeline.len()-1) as f64;
+        let filled = [0.3, 0.5, 0.9, 0.8];
+        piston_window::rectangle(filled, [0.0, y+2.0, fraction*window_size[0], SCRUBBER_HEIGHT-4.0], transform, gfx);
+    }
+
+    /// The top left corner of the minimap in the window, and pixels per tile.
+    fn minimap_layout(&self) -> ([f64; 2], f64) {
+        let view_size = self.view_size();
+        let scale = MINIMAP_SIZE / f64::max(view_size[0], view_size[1]);
+        let window_size = self.camera.window_size;
+        let corner = [window_size[0] - view_size[0]*scale - HUD_PADDING,
+                      window_size[1] - view_size[1]*scale - SCRUBBER_HEIGHT - HUD_PADDING];
+        (corner, scale)
+    }
+    fn on_minimap(&self,  cursor: [f64; 2]) -> bool {
+        let ([x, y], _) = self.minimap_layout();
+        let window_size = self.camera.window_size;
+        cursor[0] >= x  &&  cursor[1] >= y
+        &&  cursor[0] < window_size[0]-HUD_PADDING  &&  cursor[1] < window_size[1]-SCRUBBER_HEIGHT-HUD_PADDING
+    }
+    /// Moves the camera to what is under `cursor` on the minimap.
+    fn minimap_click(&mut self,  cursor: [f64; 2]) {
+        let (corner, scale) = self.minimap_layout();
+        let view_size = self.view_size();
+        let pos = [((cursor[0]-corner[0]) / scale).clamp(0.0, view_size[0]),
+                   ((cursor[1]-corner[1]) / scale).clamp(0.0, view_size[1])];
+        self.camera.center_on(pos, view_size);
+    }
+
+    /// Draws the shown floors and drones small in the bottom right corner,
+    /// with an outline of what is inside the window.
+    fn render_minimap(&mut self,  draw_state: DrawState,  transform: math::Matrix2d,  gfx: &mut GlGraphics) {
+        if self.minimap_tiles != self.sim.board.tiles {
+            let board = &self.sim.board;
+            let settings = TextureSettings::new().filter(Filter::Nearest);
+            self.minimap = (0..board.floors()).map(|z| {
+                let mut pixels = Vec::with_capacity(board.width as usize * board.height as usize * 4);
+                for y in 0..board.height {
+                    for x in 0..board.width {
+                        let color = tile_color(board[[x,y,z]]);
+                        pixels.extend(color.iter().map(|&c| (c*255.0).round() as u8 ));
+                    }
+                }
+                let size = [board.width as u32, board.height as u32];
+                Texture::create(&mut (), Format::Rgba8, &pixels, size, &settings).unwrap()
+            }).collect();
+            self.minimap_tiles.clone_from(&board.tiles);
+        }
+
+        let (corner, scale) = self.minimap_layout();
+        let view_size = self.view_size();
+        let background = [0.0, 0.0, 0.0, 0.6];
+        let border = [corner[0]-2.0, corner[1]-2.0, view_size[0]*scale+4.0, view_size[1]*scale+4.0];
+        piston_window::rectangle(background, border, transform, gfx);
+        let map_transform = transform.trans(corner[0], corner[1]).scale(scale, scale);
+        let (width, height) = (self.sim.board.width as f64, self.sim.board.height as f64);
+        for z in self.shown_floors() {
+            piston_window::Image::new().rect([self.floor_offset(z), 0.0, width, height])
+                .draw(&self.minimap[z as usize], &draw_state, map_transform, gfx);
         }
+
+        // drones as dots of at least a pixel
+        let size = f64::max(DRONE_SIZE, 1.0/scale);
+        let mut dots: Vec<RectBatch> = DroneKind::ALL.iter()
+            .map(|&kind| RectBatch::new(kind_colors(kind).0) )
+            .collect();
+        let shown = self.shown_floors();
+        let drones = &self.sim.drones;
+        for i in (0..drones.len()).filter(|&i| shown.contains(&drones.tile(i)[2]) ) {
+            let pos = drones.pos[i];
+            let x = pos[0] + self.floor_offset(pos[2] as i32);
+            dots[drones.kind[i] as usize].add([x, pos[1], size, size], map_transform);
+        }
+        for batch in &mut dots {
+            batch.draw(&draw_state, gfx);
+        }
+
+        // the part of the view inside the window
+        let first = self.camera.to_view([0.0, 0.0], view_size);
+        let last = self.camera.to_view(self.camera.window_size, view_size);
+        let first = [first[0].clamp(0.0, view_size[0]),  first[1].clamp(0.0, view_size[1])];
+        let last = [last[0].clamp(0.0, view_size[0]),  last[1].clamp(0.0, view_size[1])];
+        let outline = [corner[0] + first[0]*scale,  corner[1] + first[1]*scale,
+                       (last[0]-first[0])*scale,  (last[1]-first[1])*scale];
+        piston_window::Rectangle::new_border(color::WHITE, 1.0)
+            .draw(outline, &draw_state, transform, gfx);
     }
 
-    fn mouse_move(&mut self,  pos: Option<[i32; 2]>) {
+    fn lines_width(&mut self,  lines: &[(String, Color)]) -> f64 {
+        let mut width: f64 = 0.0;
+        for (text, _) in lines {
+            width = width.max(self.character_cache.width(HUD_FONT_SIZE, text).unwrap());
+        }
+        width
+    }
+
+    /// Draws text on a dark background that is `width` plus padding wide,
+    /// and returns its height.
+    fn render_lines(&mut self,  lines: Vec<(String, Color)>,  width: f64,
+                    draw_state: DrawState,  transform: math::Matrix2d,  gfx: &mut GlGraphics) -> f64 {
+        let line_height = HUD_FONT_SIZE as f64 + HUD_PADDING;
+        let background = [0.0, 0.0, 0.0, 0.6];
+        let size = [width + 2.0*HUD_PADDING,  lines.len() as f64 * line_height + HUD_PADDING];
+        piston_window::rectangle(background, [0.0, 0.0, size[0], size[1]], transform, gfx);
+        for (i, (text, color)) in lines.into_iter().enumerate() {
+            let baseline = (i+1) as f64 * line_height;
+            piston_window::text::Text::new_color(color, HUD_FONT_SIZE)
+                .draw(&text, &mut self.character_cache, &draw_state,
+                      transform.trans(HUD_PADDING, baseline), gfx)
+                .unwrap();
+        }
+        size[1]
+    }
+
+    fn mouse_move(&mut self,  pos: Option<[i32; 3]>) {
         self.mouse_pos = pos;
-        if pos.is_none() {// left the window
-            self.selection_start = None;
+        match (self.painting, pos) {
+            (Some(last), Some(pos)) if last != pos => {
+                // paint every tile on the way, as the mouse can skip some
+                let tiles = if last[2] == pos[2] {shapes::line(last, pos)} else {vec![pos]};
+                let tiles: Vec<[i32; 3]> = tiles.into_iter()
+                    .flat_map(|p| shapes::brush(p, self.brush_size) )
+                    .collect();
+                self.paint(&tiles);
+                self.painting = Some(pos);
+            }
+            (_, None) => {// left the window
+                self.selection_start = None;
+                self.painting = None;
+                self.history.end(&self.sim);
+            }
+            _ => {}
         }
     }
     fn mouse_press(&mut self,  button: MouseButton) {
-        if button == MouseButton::Left  &&  self.mouse_pos.is_some() {
-            self.selection_start = self.mouse_pos;
+        let pos = match (button, self.mouse_pos) {
+            (MouseButton::Left, Some(pos)) => pos,
+            _ => return,
+        };
+        if self.pasting {
+            self.paste(pos);
+            return;
+        } else if matches!(self.tool, Tool::Select | Tool::Command) {
+            self.selection_start = Some(pos); // doesn't change anything yet
+            return;
+        }
+        self.timeline.branch();
+        self.history.begin(&self.sim);
+        self.painted.clear();
+        match (self.tool, self.shape) {
+            (Tool::Target, _) | (Tool::Eyedropper, _) => self.paint(&[pos]),
+            (_, Shape::Freehand) => {
+                self.paint(&shapes::brush(pos, self.brush_size));
+                self.painting = Some(pos);
+            }
+            (_, Shape::Fill) => self.paint(&shapes::flood_fill(&self.sim.board, pos)),
+            (_, _) => self.selection_start = Some(pos),
         }
     }
 
     fn mouse_release(&mut self,  button: MouseButton) {
         match (button, self.mouse_pos) {
             (MouseButton::Left, Some(end)) => {
-                if let Some(start) = self.selection_start {
-                    self.selection_start = None;
-
-                    let from = self.board[start[1] as usize][start[0] as usize];
-                    let set = match from {Open(_)=>{Wall} Wall=>{Open(None)} Target=>{return}};
-
-                    let (first, second) = Game::order_points(start, end);
-                    for tile in self.board[first[1]as usize .. 1+second[1]as usize].iter_mut()
-                                    .flat_map(|row| row[first[0]as usize .. 1+second[0]as usize].iter_mut() )
-                                    .filter(|tile| **tile != Target ) {
-                        *tile = set;
-            }   }   }
-            (MouseButton::Right, Some(pos))  =>  {
-                let mut set = true;
-                if let Some(target) = self.target {
-                    self.board[target[1] as usize][target[0] as usize] = Open(None);
-                    self.target = None;
-                    set = pos != target;
+                match self.selection_start.take() {
+                    Some(start) if self.tool == Tool::Select => {
+                        self.selection = Some((start, [end[0], end[1], start[2]]));
+                    }
+                    Some(start) if self.tool == Tool::Command => {
+                        let (first, second) = Game::order_points(start, [end[0], end[1], start[2]]);
+                        let drones = &self.sim.drones;
+                        self.selected = (0..drones.len())
+                            .filter(|&i| {
+                                let tile = drones.tile(i);
+                                (0..3).all(|axis| tile[axis] >= first[axis]  &&  tile[axis] <= second[axis] )
+                            })
+                            .map(|i| drones.id[i] )
+                            .collect();
+                    }
+                    Some(start) => {
+                        let tiles = self.shape_tiles(start, end);
+                        self.paint(&tiles);
+                    }
+                    None => {}
                 }
-                if set {
-                    self.board[pos[1] as usize][pos[0] as usize] = Target;
-                    self.target = Some(pos);
+            }
+            (MouseButton::Right, Some(_)) if self.pasting => self.pasting = false,
+            (MouseButton::Right, Some(pos)) if self.tool == Tool::Command => {
+                if !self.selected.is_empty() {
+                    self.timeline.branch();
+                    self.sim.send_drones(&self.selected, pos);
+                }
+            }
+            (MouseButton::Right, Some(pos))  =>  {
+                self.timeline.branch();
+                self.history.begin(&self.sim);
+                if self.sim.target == Some(pos) {
+                    self.sim.move_target(None);
+                } else {
+                    self.sim.move_target(Some(pos));
                 }
             }
             (_,_) => {}
         }
-        self.update_paths();
+        if button == MouseButton::Left {
+            self.selection_start = None;
+            self.painting = None;
+        }
+        self.history.end(&self.sim);
+    }
+
+    /// The tiles the current shape covers when dragged from `start` to `end`,
+    /// or for freehand and fill when clicking `end`.
+    fn shape_tiles(&self,  start: [i32; 3],  end: [i32; 3]) -> Vec<[i32; 3]> {
+        if matches!(self.tool, Tool::Select | Tool::Command) {
+            let (first, second) = Game::order_points(start, [end[0], end[1], start[2]]);
+            return Board::positions(first, second).collect();
+        }
+        match self.shape {
+            Shape::Freehand => shapes::brush(end, self.brush_size),
+            Shape::Rectangle => {
+                let (first, second) = Game::order_points(start, end);
+                Board::positions(first, second).collect()
+            }
+            Shape::Line => shapes::line(start, end),
+            Shape::HollowRectangle => shapes::hollow_rectangle(start, end),
+            Shape::Ellipse => shapes::ellipse(start, end),
+            Shape::Fill => shapes::flood_fill(&self.sim.board, end),
+        }
+    }
+
+    /// Uses the current tool on the tiles that haven't already been painted
+    /// during this drag.
+    fn paint(&mut self,  tiles: &[[i32; 3]]) {
+        let tiles: Vec<[i32; 3]> = tiles.iter().cloned()
+            .filter(|&p| self.sim.board.contains(p)  &&  self.painted.insert(p) )
+            .collect();
+        let tile = match (tiles.first(), self.tool.tile()) {
+            (None, _) => return,
+            (Some(&pos), _) if self.tool == Tool::Eyedropper => {
+                self.pick(pos);
+                return;
+            }
+            (Some(&pos), Some(Target)) => {
+                self.sim.move_target(Some(pos));
+                return;
+            }
+            (_, tile) => tile,
+        };
+        match tile {
+            Some(Wall) => {
+                let walls: Vec<[i32; 3]> = tiles.into_iter()
+                    .filter(|&p| !matches!(self.sim.board[p], Wall | Target) )
+                    .collect();
+                self.sim.place_walls(&walls);
+            }
+            Some(tile) => {
+                for p in tiles {
+                    if self.sim.target == Some(p)  &&  tile == Open {
+                        self.sim.place_target(None);
+                    } else if self.sim.board[p] != tile  &&  self.sim.board[p] != Target {
+                        self.sim.set_tile(p, tile);
+                    }
+                    self.sim.spawner_kinds.remove(&p);
+                    if tile == Spawner {
+                        self.sim.spawner_kinds.insert(p, self.kind);
+                    }
+                }
+            }
+            None => {// drones
+                for p in tiles {
+                    if self.kind.can_enter(self.sim.board[p]) {
+                        self.sim.spawn_drone(p, self.kind);
+                    }
+                }
+            }
+        }
+        self.sim.update_paths(); // once for all the tiles
+    }
+
+    /// The tiles the selected drones will move through, following their flow field.
+    fn routes(&self) -> HashSet<[i32; 3]> {
+        let (board, drones) = (&self.sim.board, &self.sim.drones);
+        let mut route = HashSet::new();
+        let mut walked = HashSet::new();
+        for i in (0..drones.len()).filter(|&i| self.selected.contains(&drones.id[i]) ) {
+            let field = drones.field(i);
+            let mut tile = drones.tile(i);
+            // stop where another drone following the same field has been
+            while board.contains(tile)  &&  walked.insert((field, tile)) {
+                route.insert(tile);
+                match self.sim.paths[field].get(board, tile) {
+                    Some(step) if step.next != tile => tile = step.next,
+                    _ => break,
+                }
+            }
+        }
+        route
+    }
+
+    fn copy(&mut self) {
+        if let Some((a, b)) = self.selection {
+            self.clipboard = Some(Clipboard::copy(&self.sim.board, &self.sim.spawner_kinds, a, b));
+        }
+    }
+    /// Clears the selected tiles.
+    fn cut(&mut self) {
+        if let Some((a, b)) = self.selection {
+            self.timeline.branch();
+            self.history.begin(&self.sim);
+            let (first, second) = Game::order_points(a, b);
+            for p in Board::positions(first, second) {
+                if self.sim.target == Some(p) {
+                    self.sim.move_target(None);
+                } else if self.sim.board[p] != Open {
+                    self.sim.set_tile(p, Open);
+                }
+                self.sim.spawner_kinds.remove(&p);
+            }
+            self.sim.update_paths();
+            self.history.end(&self.sim);
+        }
+    }
+    /// Places the clipboard with its top left corner at `at`.
+    fn paste(&mut self,  at: [i32; 3]) {
+        let placed = match &self.clipboard {
+            Some(clipboard) => clipboard.placed(at)
+                .filter(|&(p, _, _)| self.sim.board.contains(p) )
+                .collect::<Vec<_>>(),
+            None => return,
+        };
+        self.timeline.branch();
+        self.history.begin(&self.sim);
+        let mut walls = Vec::new();
+        for (p, tile, kind) in placed {
+            match tile {
+                Wall => walls.push(p),
+                Target => self.sim.move_target(Some(p)),
+                tile => {
+                    if self.sim.target == Some(p) {
+                        self.sim.move_target(None);
+                    }
+                    if self.sim.board[p] != tile {
+                        self.sim.set_tile(p, tile);
+                    }
+                }
+            }
+            self.sim.spawner_kinds.remove(&p);
+            if let Some(kind) = kind {
+                self.sim.spawner_kinds.insert(p, kind);
+            }
+        }
+        // keep the target, like the refuse wall policy keeps drones
+        walls.retain(|&p| self.sim.target != Some(p) );
+        self.sim.build_walls(&walls); // also updates the paths
+        self.history.end(&self.sim);
+    }
+    /// Writes the clipboard as text to `CLIPBOARD_FILE` and prints it.
+    fn export_clipboard(&self) {
+        if let Some(clipboard) = &self.clipboard {
+            print!("{}", clipboard);
+            match fs::write(CLIPBOARD_FILE, clipboard.to_string()) {
+                Ok(()) => println!("copied to {}", CLIPBOARD_FILE),
+                Err(e) => eprintln!("cannot write {}: {}", CLIPBOARD_FILE, e),
+            }
+        }
+    }
+    /// Reads the clipboard from `CLIPBOARD_FILE`, and starts pasting it.
+    fn import_clipboard(&mut self) {
+        let clipboard = fs::read_to_string(CLIPBOARD_FILE)
+            .map_err(|e| e.to_string() )
+            .and_then(|text| Clipboard::parse(&text) );
+        match clipboard {
+            Ok(clipboard) => {
+                self.clipboard = Some(clipboard);
+                self.pasting = true;
+            }
+            Err(e) => eprintln!("cannot read {}: {}", CLIPBOARD_FILE, e),
+        }
+    }
+
+    /// Selects the tool, and for spawners also the kind, that would place `pos`.
+    fn pick(&mut self,  pos: [i32; 3]) {
+        let tile = self.sim.board[pos];
+        if let Some(&tool) = Tool::ALL.iter().find(|tool| tool.tile() == Some(tile) ) {
+            self.tool = tool;
+        }
+        if let Some(&kind) = self.sim.spawner_kinds.get(&pos) {
+            self.kind = kind;
+        }
+    }
+
+    /// Writes the board, drones, settings and edit history to `map_file`.
+    fn save(&self) {
+        let scenario = Scenario {
+            seed: Some(self.sim.seed()),
+            board: self.sim.board.clone(),
+            drones: self.sim.drones.iter().collect(),
+            spawner_kinds: self.sim.spawner_kinds.clone(),
+            settings: self.sim.settings.clone(),
+            history: self.history.clone(),
+        };
+        match fs::write(&self.map_file, scenario.to_string()) {
+            Ok(()) => println!("saved to {}", self.map_file),
+            Err(e) => eprintln!("cannot save to {}: {}", self.map_file, e),
+        }
     }
 
     fn key_press(&mut self,  key: Key) {
-        if key == Key::P {
-            self.paused = !self.paused;
+        if self.ctrl {
+            match key {
+                Key::Z => {
+                    self.timeline.branch();
+                    self.history.undo(&mut self.sim);
+                }
+                Key::Y => {
+                    self.timeline.branch();
+                    self.history.redo(&mut self.sim);
+                }
+                Key::S => self.save(),
+                Key::C => self.copy(),
+                Key::X => {
+                    self.copy();
+                    self.cut();
+                }
+                Key::V => self.pasting = self.clipboard.is_some()  &&  !self.pasting,
+                Key::E => self.export_clipboard(),
+                Key::O => self.import_clipboard(),
+                _ => {}
+            }
+            return;
+        }
+        match key {
+            Key::LCtrl | Key::RCtrl => self.ctrl = true,
+            Key::P => self.paused = !self.paused,
+            Key::RightBracket => self.speed = f64::min(self.speed*2.0, MAX_SPEED),
+            Key::LeftBracket => self.speed = f64::max(self.speed/2.0, MIN_SPEED),
+            Key::M => self.max_speed = !self.max_speed,
+            Key::Period if self.paused => {
+                if self.step() {
+                    self.timeline.record(&self.sim);
+                }
+                self.time = self.update_time - UPDATE_TIME/self.speed; // show where they ended up
+            }
+            Key::Comma => {
+                self.paused = true;
+                self.timeline.back(&mut self.sim);
+                self.time = self.update_time - UPDATE_TIME/self.speed;
+            }
+            Key::F => {
+                self.timeline.branch();
+                self.sim.settings.movement = match self.sim.settings.movement {
+                    Movement::Classic => Movement::Steering,
+                    Movement::Steering => Movement::Classic,
+                };
+            }
+            Key::I => self.interpolation = match self.interpolation {
+                Interpolation::Off => Interpolation::Linear,
+                Interpolation::Linear => Interpolation::Eased,
+                Interpolation::Eased => Interpolation::Off,
+            },
+            Key::D1 | Key::D2 | Key::D3 | Key::D4 | Key::D5
+            | Key::D6 | Key::D7 | Key::D8 | Key::D9 | Key::D0 => {
+                // D0 comes before D1 in Key, but after D9 on the keyboard
+                let i = (key as usize + 10 - Key::D1 as usize) % 10;
+                self.tool = Tool::ALL[i];
+            }
+            Key::Backquote => self.tool = Tool::Select,
+            Key::G => self.tool = Tool::Command,
+            Key::T => self.pinned = match (self.pinned, self.mouse_pos) {
+                (None, Some(tile)) => Some((tile, self.hovered_drone())),
+                _ => None,
+            },
+            Key::Delete if !self.selected.is_empty() => {
+                self.timeline.branch();
+                self.sim.remove_drones(&self.selected);
+                self.selected.clear();
+            }
+            Key::Left | Key::Right | Key::Up | Key::Down => {
+                let step = [self.camera.window_size[0]*PAN_STEP,  self.camera.window_size[1]*PAN_STEP];
+                let by = match key {
+                    Key::Left => [step[0], 0.0],
+                    Key::Right => [-step[0], 0.0],
+                    Key::Up => [0.0, step[1]],
+                    _ => [0.0, -step[1]],
+                };
+                let view_size = self.view_size();
+                self.camera.pan(by, view_size);
+            }
+            Key::Home => self.camera.fitted = true,
+            Key::R => if let Some(clipboard) = &mut self.clipboard {
+                clipboard.rotate();
+            },
+            Key::H => if let Some(clipboard) = &mut self.clipboard {
+                clipboard.flip_horizontally();
+            },
+            Key::J => if let Some(clipboard) = &mut self.clipboard {
+                clipboard.flip_vertically();
+            },
+            Key::Q => {
+                let i = Shape::ALL.iter().position(|&s| s == self.shape ).unwrap();
+                self.shape = Shape::ALL[(i+1) % Shape::ALL.len()];
+                self.selection_start = None;
+            }
+            Key::Semicolon => self.brush_size = cmp::max(self.brush_size-1, 1),
+            Key::Quote => self.brush_size = cmp::min(self.brush_size+1, MAX_BRUSH_SIZE),
+            Key::B => {
+                self.timeline.branch();
+                self.sim.settings.battery = match self.sim.settings.battery {
+                    Some(_) => None,
+                    None => Some(BATTERY),
+                };
+            }
+            Key::K => {
+                let i = DroneKind::ALL.iter().position(|&k| k == self.kind ).unwrap();
+                self.kind = DroneKind::ALL[(i+1) % DroneKind::ALL.len()];
+                if let Some(pos) = self.mouse_pos.filter(|&pos| self.sim.board[pos] == Spawner ) {
+                    self.timeline.branch();
+                    self.history.begin(&self.sim);
+                    self.sim.spawner_kinds.insert(pos, self.kind);
+                    self.history.end(&self.sim);
+                }
+            }
+            Key::A => {
+                self.timeline.branch();
+                let current = self.sim.settings.on_arrival;
+                let i = Arrival::ALL.iter().position(|&a| a == current ).unwrap();
+                self.sim.settings.on_arrival = Arrival::ALL[(i+1) % Arrival::ALL.len()];
+            }
+            Key::N => {
+                self.timeline.branch();
+                let current = self.sim.settings.fallback;
+                let i = Fallback::ALL.iter().position(|&f| f == current ).unwrap();
+                self.sim.settings.fallback = Fallback::ALL[(i+1) % Fallback::ALL.len()];
+                self.sim.update_paths();
+            }
+            Key::W => {
+                self.timeline.branch();
+                let current = self.sim.settings.on_wall;
+                let i = WallPolicy::ALL.iter().position(|&p| p == current ).unwrap();
+                self.sim.settings.on_wall = WallPolicy::ALL[(i+1) % WallPolicy::ALL.len()];
+            }
+            Key::Tab => self.show_stats = !self.show_stats,
+            Key::V => {
+                self.side_by_side = !self.side_by_side;
+                self.mouse_pos = None; // the tile under the cursor has changed
+                self.selection_start = None;
+            }
+            Key::PageUp if self.visible_floor+1 < self.sim.board.floors() => {
+                self.visible_floor += 1;
+                self.mouse_pos = None;
+            }
+            Key::PageDown if self.visible_floor > 0 => {
+                self.visible_floor -= 1;
+                self.mouse_pos = None;
+            }
+            Key::Equals | Key::NumPadPlus => {
+                self.timeline.branch();
+                self.history.begin(&self.sim);
+                self.sim.add_floor();
+                self.history.end(&self.sim);
+                self.visible_floor = self.sim.board.floors()-1;
+                self.mouse_pos = None;
+            }
+            Key::Minus | Key::NumPadMinus if self.sim.board.floors() > 1 => {
+                self.timeline.branch();
+                self.history.begin(&self.sim);
+                self.sim.remove_floor();
+                self.history.end(&self.sim);
+                self.visible_floor = cmp::min(self.visible_floor, self.sim.board.floors()-1);
+                self.mouse_pos = None;
+                self.selection_start = None;
+                self.selection = None;
+            }
+            _ => {}
+        }
+    }
+    fn key_release(&mut self,  key: Key) {
+        if key == Key::LCtrl  ||  key == Key::RCtrl {
+            self.ctrl = false;
+        }
+    }
+}
+
+
+/// Where ctrl+s saves the map if it wasn't loaded from a file
+const DEFAULT_MAP_FILE: &str = "map.txt";
+/// Where ctrl+e writes the clipboard and ctrl+o reads it from
+const CLIPBOARD_FILE: &str = "clipboard.txt";
+
+const USAGE: &str = "usage: path [--seed NUMBER] [--script FILE] [--events-csv FILE] [--events-jsonl FILE] [SCENARIO_FILE]";
+
+struct Args {
+    scenario: Scenario,
+    /// The scenario file, if any
+    scenario_file: Option<String>,
+    seed: Option<u64>,
+    /// Rhai script file
+    script: Option<String>,
+    /// Files to write events to
+    events_csv: Option<String>,
+    events_jsonl: Option<String>,
+}
+
+/// Reads the scenario file, seed and event files from the command line,
+/// and exits on errors.
+fn parse_args() -> Args {
+    let mut scenario = None;
+    let mut scenario_file = None;
+    let mut seed = None;
+    let mut events_csv = None;
+    let mut events_jsonl = None;
+    let mut script = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--seed" {
+            match args.next().map(|n| n.parse::<u64>() ) {
+                Some(Ok(n)) => seed = Some(n),
+                _ => {
+                    eprintln!("--seed needs a positive integer");
+                    process::exit(2);
+                }
+            }
+        } else if arg == "--script" {
+            script = Some(args.next().unwrap_or_else(|| {
+                eprintln!("--script needs a file name");
+                process::exit(2);
+            }));
+        } else if arg == "--events-csv"  ||  arg == "--events-jsonl" {
+            let file = args.next().unwrap_or_else(|| {
+                eprintln!("{} needs a file name", arg);
+                process::exit(2);
+            });
+            if arg == "--events-csv" {
+                events_csv = Some(file);
+            } else {
+                events_jsonl = Some(file);
+            }
+        } else if arg.starts_with('-')  ||  scenario.is_some() {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        } else {
+            let text = fs::read_to_string(&arg).unwrap_or_else(|e| {
+                eprintln!("cannot read {}: {}", arg, e);
+                process::exit(1);
+            });
+            scenario = Some(Scenario::parse(&text).unwrap_or_else(|e| {
+                eprintln!("{}: {}", arg, e);
+                process::exit(1);
+            }));
+            scenario_file = Some(arg);
         }
     }
+    Args{scenario: scenario.unwrap_or_default(),  scenario_file,  seed,  script,  events_csv,  events_jsonl}
 }
 
+fn create_file(name: &str) -> BufWriter<File> {
+    match File::create(name) {
+        Ok(file) => BufWriter::new(file),
+        Err(e) => {
+            eprintln!("cannot create {}: {}", name, e);
+            process::exit(1);
+        }
+    }
+}
 
 // Handles setup, resize and converting mouse coordinates to tile coordinates.
 fn main() {
-    println!("Left click to place or remove walls,");
-    println!(" drag to select multiple tiles.");
-    println!("Right click to move or remove tha yellow target.");
-    println!("Press p to pause");
+    let mut args = parse_args();
+    let history = mem::take(&mut args.scenario.history);
+    let map_file = args.scenario_file.unwrap_or_else(|| DEFAULT_MAP_FILE.to_string() );
+    // the command line overrides the scenario file
+    let seed = args.seed.or(args.scenario.seed).unwrap_or_else(rand::random);
+    println!("seed: {}", seed);
+    let mut sim = Simulation::new(args.scenario, seed);
+    if let Some(name) = args.events_csv {
+        let writer = CsvWriter::new(create_file(&name)).unwrap_or_else(|e| {
+            eprintln!("cannot write to {}: {}", name, e);
+            process::exit(1);
+        });
+        sim.subscribe(writer);
+    }
+    if let Some(name) = args.events_jsonl {
+        sim.subscribe(JsonLinesWriter::new(create_file(&name)));
+    }
+    #[cfg(feature="scripting")]
+    let script = args.script.map(|name| {
+        let mut script = Script::load(name.as_ref()).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        });
+        if let Err(e) = script.start(&mut sim) {
+            eprintln!("{}", e); // keep running, it might be fixed and reloaded
+        }
+        script
+    });
+    #[cfg(not(feature="scripting"))]
+    if args.script.is_some() {
+        eprintln!("--script needs building with --features scripting");
+        process::exit(2);
+    }
+
+    println!("Left click or drag to use the current tool, and right click to move or remove the target.");
+    println!("Press 1 to 9 and 0 or click the toolbar to choose wall, erase, mud, stairs, elevator,");
+    println!(" charger, target, spawner, drone or eyedropper (which picks the tool for a clicked tile),");
+    println!(" q to switch between painting freehand, dragging a rectangle, line, hollow rectangle");
+    println!("   or ellipse, and filling the clicked area,");
+    println!(" ; or ' to make the freehand brush smaller or larger.");
+    println!("Press ` for the select tool, then ctrl+c or ctrl+x to copy or cut the selection");
+    println!(" and ctrl+v to paste it with left click, r to rotate it and h or j to flip it.");
+    println!(" Right click stops pasting. Ctrl+e writes what was copied to {}, and ctrl+o reads it.", CLIPBOARD_FILE);
+    println!("Press g to select drones by clicking or dragging, then right click to send them there");
+    println!(" or press delete to remove them.");
+    println!("Press b to turn batteries on or off,");
+    println!(" k to change the kind of drone whose distances are shown and that new spawners and drones are,");
+    println!("   and of the hovered spawner,");
+    println!(" a to change what happens when drones reach the target,");
+    println!(" w to change what happens to drones when walls are placed on them,");
+    println!(" n to change what drones that cannot reach the target do,");
+    println!(" + or - to add or remove the top floor,");
+    println!(" page up or page down to change the visible floor,");
+    println!(" v to show all floors side by side.");
+    println!("Scroll to zoom, drag with the middle button or press the arrow keys to move around,");
+    println!(" and press home to fit everything in the window again.");
+    println!(" Clicking or dragging in the map in the bottom right corner also moves there.");
+    println!("Press p to pause, and . to advance one tick while paused.");
+    println!("Press , to go back in time, and click the bar at the bottom to jump;");
+    println!(" editing after going back continues from there.");
+    println!("Press ctrl+z or ctrl+y to undo or redo edits,");
+    println!(" and ctrl+s to save the map with its edit history to {}.", map_file);
+    println!("Press [ or ] to halve or double the speed, or m to run as fast as possible.");
+    println!("Press i to change how drones are drawn between tiles,");
+    println!(" and f to switch between moving one tile at a time and steering physics.");
+    println!("Press tab to show or hide statistics.");
+    println!("Hovering shows the distance and drones of a tile, and press t to keep showing the hovered");
+    println!(" tile and drone below the speed, or t again to go back to following the cursor.");
 
     let window_size = [
-        INITIAL_TILE_SIZE as u32  *  BOARD_WIDTH as u32,
-        INITIAL_TILE_SIZE as u32  *  BOARD_HEIGHT as u32
+        INITIAL_TILE_SIZE as u32  *  sim.board.width as u32,
+        INITIAL_TILE_SIZE as u32  *  sim.board.height as u32
     ];
     let mut window: PistonWindow = // <GlutinWindow>
         WindowSettings::new("PistonPath", window_size)
@@ -383,13 +1750,20 @@ fn main() {
 
     let mut gfx = GlGraphics::new(OpenGL::V3_2);
 
-    let mut tile_size = INITIAL_TILE_SIZE; // changes if window is resized
-    let mut offset = [0.0; 2]; // letterboxing after resize
+    let mut window_size = [window_size[0] as f64, window_size[1] as f64]; // changes if window is resized
 
-    let mut font_requirements: FontProperty = FontPropertyBuilder::new().family(FONT_NAME).build();
-    let font_data: Vec<u8> = font_loader::system_fonts::get(&mut font_requirements).unwrap().0;
+    let font_requirements: FontProperty = FontPropertyBuilder::new().family(FONT_NAME).build();
+    let font_data: Vec<u8> = font_loader::system_fonts::get(&font_requirements).unwrap().0;
 
-    let mut game = Game::new(&*font_data);
+    let mut game = Game::new(&font_data, sim, history, map_file, window_size);
+    #[cfg(feature="scripting")]
+    {
+        game.script = script;
+    }
+    let mut cursor = [0.0, 0.0]; // in window coordinates
+    let mut panning = false; // dragging with the middle button
+    let mut on_minimap = false; // dragging with the left button started on the minimap
+    let mut title = game.title();
     let mut frames = 0;
     let started = Instant::now();
     let mut event_loop: Events = window.events;
@@ -404,6 +1778,7 @@ fn main() {
                 //     prev_view = Some(viewport);
                 // }
                 frames += 1;
+                let (tile_size, offset) = game.camera.layout(game.view_size());
 
                 // An optimization introduced in opengl_graphics 0.39.1 causes
                 // severe glitching if not wrapped in .draw.
@@ -411,7 +1786,8 @@ fn main() {
                 gfx.draw(viewport, |context, gfx| {
                     let context: Context = context;
                     let gfx: &mut GlGraphics = gfx; // the same instance as outside
-                    // Handle resized windows by scaling and letterboxing.
+                    let window_context = context;
+                    // Zoom and pan, or scale and letterbox to fit.
                     let context: Context = context.trans(offset[0], offset[1])
                                                   .scale(tile_size, tile_size);
 
@@ -422,6 +1798,17 @@ fn main() {
                     // existing color in tile, and blend with that.
                     context.draw_state.blend(Blend::Alpha);
                     game.render(context.draw_state, context.transform, gfx);
+                    let hud_height = game.render_hud(window_context.draw_state, window_context.transform, gfx);
+                    if game.show_stats {
+                        game.render_stats(window_size[0], window_context.draw_state,
+                                          window_context.transform, gfx);
+                    }
+                    game.render_toolbar(window_size, window_context.draw_state, window_context.transform, gfx);
+                    game.render_minimap(window_context.draw_state, window_context.transform, gfx);
+                    game.render_inspector(hud_height, window_context.draw_state, window_context.transform, gfx);
+                    if game.show_scrubber() {
+                        game.render_scrubber(window_size, window_context.transform, gfx);
+                    }
                 });
             }
             Event::Loop(Loop::Update(UpdateArgs{dt})) => {
@@ -430,31 +1817,53 @@ fn main() {
 
             Event::Input(Input::Button(ButtonArgs{state,button,..}), _) => {
                 match (button, state) {
-                    (Button::Keyboard(key), ButtonState::Press) => game.key_press(key),
+                    (Button::Keyboard(key), ButtonState::Press) => {
+                        game.key_press(key);
+                        game.hover(cursor); // the camera might have moved
+                    }
+                    (Button::Keyboard(key), ButtonState::Release) => game.key_release(key),
+                    (Button::Mouse(MouseButton::Left), ButtonState::Press)
+                    if game.show_scrubber()  &&  cursor[1] >= window_size[1]-SCRUBBER_HEIGHT => {
+                        game.seek(cursor[0] / window_size[0]);
+                    }
+                    (Button::Mouse(MouseButton::Left), ButtonState::Press)
+                    if game.toolbar_click(cursor, window_size) => {}
+                    (Button::Mouse(MouseButton::Left), ButtonState::Press) if game.on_minimap(cursor) => {
+                        on_minimap = true;
+                        game.minimap_click(cursor);
+                        game.hover(cursor);
+                    }
+                    (Button::Mouse(MouseButton::Left), ButtonState::Release) if on_minimap => on_minimap = false,
+                    (Button::Mouse(MouseButton::Middle), state) => panning = state == ButtonState::Press,
                     (Button::Mouse(button), ButtonState::Press) => game.mouse_press(button),
                     (Button::Mouse(button), ButtonState::Release) => game.mouse_release(button),
                     _ => {}
                 }
+                if game.title() != title {
+                    title = game.title();
+                    window.set_title(title.clone());
+                }
             }
             Event::Input(Input::Resize(ResizeArgs{window_size: [x,y], ..}), _) => {
-                let (x,y): (f64,f64) = (x,y);
-                tile_size = f64::min(x / (BOARD_WIDTH as f64),
-                                     y / (BOARD_HEIGHT as f64));
-                offset = [(x - tile_size*BOARD_WIDTH as f64) / 2.0,
-                          (y - tile_size*BOARD_HEIGHT as f64) / 2.0];
-                //println!("x: {}, y: {}, tile_size: {}, offset: [{}, {}]", x, y, tile_size, offset[0], offset[1]);
+                window_size = [x,y];
+                game.camera.window_size = window_size;
             }
             Event::Input(Input::Move(Motion::MouseCursor([x,y])), _) => {
                 let (x,y): (f64,f64) = (x,y);
-                let mut pos = None;
-                // compare floats to avoid rounding at the edges
-                let x = (x - offset[0]) / tile_size;
-                let y = (y - offset[1]) / tile_size;
-                if x >= 0.0  &&  x < BOARD_WIDTH as f64
-                && y >= 0.0  &&  y < BOARD_HEIGHT as f64 {
-                    pos = Some([x as i32, y as i32]);
+                if panning {
+                    let view_size = game.view_size();
+                    game.camera.pan([x-cursor[0], y-cursor[1]], view_size);
                 }
-                game.mouse_move(pos);
+                if on_minimap {
+                    game.minimap_click([x,y]);
+                }
+                cursor = [x,y];
+                game.hover(cursor);
+            }
+            Event::Input(Input::Move(Motion::MouseScroll([_, steps])), _) => {
+                let view_size = game.view_size();
+                game.camera.zoom(ZOOM_STEP.powf(steps), cursor, view_size);
+                game.hover(cursor);
             }
             Event::Input(Input::Cursor(false), _) => {
                 // cursor left window, only triggered if a button is pressed.
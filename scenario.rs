@@ -0,0 +1,456 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Reading the starting state of a simulation from a text file.
+//!
+//! The format is line based, and lines starting with `//` are ignored:
+//!
+//! ```text
+//! // optional, a random seed is used if not given
+//! seed 1234
+//! // every floor starts with a line with only `floor`, followed by one line per row
+//! floor
+//! ....#....
+//! .T..#..S.
+//! ....#....
+//! floor
+//! .......S.
+//! .........
+//! .........
+//! // drones are positioned by their top left corner: x y floor,
+//! // optionally followed by wheeled, tracked or flying
+//! drone 0.3 0.3 0
+//! drone 2.3 0.3 0 flying
+//! // the kind of drones a spawner creates: x y floor kind
+//! spawner 7 0 1 tracked
+//! // settings that aren't given keep their default value
+//! spawn_interval 10
+//! spawn_burst 1
+//! // a number or none
+//! spawn_cap none
+//! // despawn, clone, respawn or idle
+//! on_arrival despawn
+//! // what happens to drones when walls are placed on them: destroy, relocate or refuse
+//! on_wall relocate
+//! max_drones 10000
+//! // how many tiles drones can move before they must charge, or none
+//! battery 60
+//! // drones with less energy than this go to the nearest charger
+//! low_battery 20
+//! // energy gained per tick on a charger
+//! charge_rate 5
+//! // classic or steering
+//! movement classic
+//! // what drones that cannot reach the target do:
+//! // jitter, wait, random_walk, explore or closest
+//! fallback explore
+//! // edits that can be undone, oldest first: floors before and after, then
+//! // changed tiles as `tile x y floor before after` and changed spawners as
+//! // `kind x y floor before after`, where `-` is the default kind
+//! undo 1 1 tile 4 0 0 . # tile 4 1 0 . #
+//! undo 2 1 tile 7 0 1 + . kind 7 0 1 tracked -
+//! // undone edits, the next one to redo first
+//! redo 1 1 tile 2 2 0 # .
+//! ```
+//!
+//! In rows, `.` is an open tile, `#` a wall, `T` the target,
+//! `S` stairs, `E` an elevator, `+` a spawner, `~` mud and `C` a charger.
+//!
+//! Saving writes the same format with `Display`.
+
+use std::fmt;
+use std::collections::HashMap;
+use std::error::Error;
+use board::{Board,Tile};
+use board::Tile::*;
+use sim::{Settings,Arrival,WallPolicy,Movement};
+use fallback::Fallback;
+use drones::Drone;
+use kind::DroneKind;
+use history::{History,Edit};
+
+pub const DEFAULT_WIDTH: i32 = 20;
+pub const DEFAULT_HEIGHT: i32 = 15;
+
+
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    pub seed: Option<u64>,
+    pub board: Board,
+    pub drones: Vec<Drone>,
+    /// Spawners that don't create the default kind.
+    pub spawner_kinds: HashMap<[i32; 3], DroneKind>,
+    pub settings: Settings,
+    pub history: History,
+}
+
+/// The original hard-coded board: a single empty floor with the target in
+/// the center, and a drone in each corner.
+impl Default for Scenario {
+    fn default() -> Scenario {
+        let (width, height) = (DEFAULT_WIDTH, DEFAULT_HEIGHT);
+        let mut board = Board::new(width, height, 1);
+        board[[width/2, height/2, 0]] = Target;
+        Scenario {
+            seed: None,
+            board,
+            // put a drone in the center of each corner tile
+            drones: vec![
+                Drone::new([0.3, 0.3, 0.0]),
+                Drone::new([0.3, height as f64-0.7, 0.0]),
+                Drone::new([width as f64-0.7, 0.3, 0.0]),
+                Drone::new([width as f64-0.7, height as f64-0.7, 0.0]),
+            ],
+            spawner_kinds: HashMap::new(),
+            settings: Settings::default(),
+            history: History::default(),
+        }
+    }
+}
+
+
+#[derive(Clone, PartialEq,Eq, Debug)]
+pub struct ParseError {
+    /// Starts at 1
+    pub line: usize,
+    pub message: String,
+} impl fmt::Display for ParseError {
+    fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmtr, "line {}: {}", self.line, self.message)
+    }
+} impl Error for ParseError {}
+
+
+/// The tile a character in a row stands for.
+pub fn tile_from_char(c: char) -> Option<Tile> {
+    match c {
+        '.' => Some(Open),
+        '#' => Some(Wall),
+        'T' => Some(Target),
+        'S' => Some(Stairs),
+        'E' => Some(Elevator),
+        '+' => Some(Spawner),
+        '~' => Some(Mud),
+        'C' => Some(Charger),
+        _ => None,
+    }
+}
+
+/// The character that stands for a tile in rows.
+pub fn tile_char(tile: Tile) -> char {
+    match tile {
+        Open => '.',
+        Wall => '#',
+        Target => 'T',
+        Stairs => 'S',
+        Elevator => 'E',
+        Spawner => '+',
+        Mud => '~',
+        Charger => 'C',
+    }
+}
+
+fn kind_from_name(name: &str) -> Option<DroneKind> {
+    DroneKind::ALL.iter().cloned().find(|kind| kind.name() == name )
+}
+
+/// Parses what follows `undo` or `redo`.
+fn parse_edit<'a>(mut words: impl Iterator<Item=&'a str>) -> Result<Edit, String> {
+    fn number(word: Option<&str>) -> Result<i32, String> {
+        let word = word.ok_or_else(|| "edit ends early".to_string() )?;
+        word.parse().map_err(|e| format!("invalid number {:?}: {}", word, e) )
+    }
+    fn tile(word: Option<&str>) -> Result<Tile, String> {
+        let word = word.ok_or_else(|| "edit ends early".to_string() )?;
+        let mut chars = word.chars();
+        match (chars.next().and_then(tile_from_char), chars.next()) {
+            (Some(tile), None) => Ok(tile),
+            _ => Err(format!("unknown tile {:?}", word)),
+        }
+    }
+    fn kind(word: Option<&str>) -> Result<Option<DroneKind>, String> {
+        match word {
+            Some("-") => Ok(None),
+            Some(name) => kind_from_name(name).map(Some).ok_or_else(|| format!("unknown drone kind {:?}", name) ),
+            None => Err("edit ends early".to_string()),
+        }
+    }
+    let floors = (number(words.next())?, number(words.next())?);
+    let mut edit = Edit{floors,  tiles: Vec::new(),  spawner_kinds: Vec::new()};
+    while let Some(word) = words.next() {
+        let pos = [number(words.next())?, number(words.next())?, number(words.next())?];
+        match word {
+            "tile" => edit.tiles.push((pos, tile(words.next())?, tile(words.next())?)),
+            "kind" => edit.spawner_kinds.push((pos, kind(words.next())?, kind(words.next())?)),
+            _ => return Err(format!("expected tile or kind, not {:?}", word)),
+        }
+    }
+    Ok(edit)
+}
+
+fn write_edit(fmtr: &mut fmt::Formatter,  keyword: &str,  edit: &Edit) -> fmt::Result {
+    write!(fmtr, "{} {} {}", keyword, edit.floors.0, edit.floors.1)?;
+    for &([x,y,z], from, to) in &edit.tiles {
+        write!(fmtr, " tile {} {} {} {} {}", x, y, z, tile_char(from), tile_char(to))?;
+    }
+    let name = |kind: Option<DroneKind>| kind.map_or("-", DroneKind::name);
+    for &([x,y,z], from, to) in &edit.spawner_kinds {
+        write!(fmtr, " kind {} {} {} {} {}", x, y, z, name(from), name(to))?;
+    }
+    writeln!(fmtr)
+}
+
+impl Scenario {
+    pub fn parse(text: &str) -> Result<Scenario, ParseError> {
+        let mut seed = None;
+        let mut settings = Settings::default();
+        let mut floors: Vec<Vec<Vec<Tile>>> = Vec::new();
+        let mut drones: Vec<(usize, Drone)> = Vec::new();
+        let mut spawner_kinds: Vec<(usize, [i32; 3], DroneKind)> = Vec::new();
+        let mut edits: Vec<(usize, Edit)> = Vec::new();
+        let mut history = History::default();
+        let mut width = None;
+        let mut in_floor = false;
+        for (line, content) in text.lines().enumerate() {
+            let line = line+1;
+            let error = |message: String| ParseError{line, message};
+            let content = content.trim();
+            if content.is_empty()  ||  content.starts_with("//") {
+                continue;
+            }
+            let mut words = content.split_whitespace();
+            match words.next().unwrap() {
+                "floor" => {
+                    floors.push(Vec::new());
+                    in_floor = true;
+                }
+                "seed" => {
+                    let value = words.next().ok_or_else(|| error("seed without value".to_string()) )?;
+                    seed = Some(value.parse().map_err(|e| error(format!("invalid seed: {}", e)) )?);
+                    in_floor = false;
+                }
+                "drone" => {
+                    let mut pos = [0.0; 3];
+                    for coordinate in &mut pos {
+                        let value = words.next().ok_or_else(|| error("drone needs x, y and floor".to_string()) )?;
+                        *coordinate = value.parse().map_err(|e| error(format!("invalid drone position: {}", e)) )?;
+                    }
+                    let kind = match words.next() {
+                        Some(name) => kind_from_name(name).ok_or_else(|| error(format!("unknown drone kind {:?}", name)) )?,
+                        None => DroneKind::default(),
+                    };
+                    drones.push((line, Drone{kind,  ..Drone::new(pos)}));
+                    in_floor = false;
+                }
+                "spawner" => {
+                    let mut pos = [0; 3];
+                    for coordinate in &mut pos {
+                        let value = words.next().ok_or_else(|| error("spawner needs x, y, floor and kind".to_string()) )?;
+                        *coordinate = value.parse().map_err(|e| error(format!("invalid spawner position: {}", e)) )?;
+                    }
+                    let name = words.next().ok_or_else(|| error("spawner needs x, y, floor and kind".to_string()) )?;
+                    let kind = kind_from_name(name).ok_or_else(|| error(format!("unknown drone kind {:?}", name)) )?;
+                    spawner_kinds.push((line, pos, kind));
+                    in_floor = false;
+                }
+                setting @ "spawn_interval" | setting @ "spawn_burst" | setting @ "spawn_cap" | setting @ "max_drones"
+                | setting @ "battery" | setting @ "low_battery" | setting @ "charge_rate" => {
+                    let value = words.next().ok_or_else(|| error(format!("{} without value", setting)) )?;
+                    let invalid = |e| error(format!("invalid {}: {}", setting, e));
+                    match setting {
+                        "spawn_interval" => settings.spawn_interval = value.parse().map_err(invalid)?,
+                        "spawn_burst" => settings.spawn_burst = value.parse().map_err(invalid)?,
+                        "spawn_cap" if value == "none" => settings.spawn_cap = None,
+                        "spawn_cap" => settings.spawn_cap = Some(value.parse().map_err(invalid)?),
+                        "battery" if value == "none" => settings.battery = None,
+                        "battery" => settings.battery = Some(value.parse().map_err(invalid)?),
+                        "low_battery" => settings.low_battery = value.parse().map_err(invalid)?,
+                        "charge_rate" => settings.charge_rate = value.parse().map_err(invalid)?,
+                        _ => settings.max_drones = value.parse().map_err(invalid)?,
+                    }
+                    in_floor = false;
+                }
+                "fallback" => {
+                    let value = words.next().ok_or_else(|| error("fallback without value".to_string()) )?;
+                    settings.fallback = *Fallback::ALL.iter()
+                        .find(|fallback| fallback.name() == value )
+                        .ok_or_else(|| error(format!("unknown fallback {:?}", value)) )?;
+                    in_floor = false;
+                }
+                keyword @ "undo" | keyword @ "redo" => {
+                    let edit = parse_edit(words).map_err(error)?;
+                    edits.push((line, edit.clone()));
+                    if keyword == "undo" {
+                        history.undo.push(edit);
+                    } else {
+                        history.redo.insert(0, edit);
+                    }
+                    in_floor = false;
+                }
+                "on_arrival" => {
+                    let value = words.next().ok_or_else(|| error("on_arrival without value".to_string()) )?;
+                    settings.on_arrival = *Arrival::ALL.iter()
+                        .find(|arrival| arrival.name() == value )
+                        .ok_or_else(|| error(format!("unknown on_arrival {:?}", value)) )?;
+                    in_floor = false;
+                }
+                "on_wall" => {
+                    let value = words.next().ok_or_else(|| error("on_wall without value".to_string()) )?;
+                    settings.on_wall = *WallPolicy::ALL.iter()
+                        .find(|policy| policy.name() == value )
+                        .ok_or_else(|| error(format!("unknown on_wall {:?}", value)) )?;
+                    in_floor = false;
+                }
+                "movement" => {
+                    let value = words.next().ok_or_else(|| error("movement without value".to_string()) )?;
+                    settings.movement = *Movement::ALL.iter()
+                        .find(|movement| movement.name() == value )
+                        .ok_or_else(|| error(format!("unknown movement {:?}", value)) )?;
+                    in_floor = false;
+                }
+                _ if in_floor => {
+                    let row = content.chars()
+                        .map(|c| tile_from_char(c).ok_or_else(|| error(format!("unknown tile {:?}", c)) ) )
+                        .collect::<Result<Vec<Tile>,_>>()?;
+                    let width = *width.get_or_insert(row.len());
+                    if row.len() != width {
+                        return Err(error(format!("row is {} tiles wide, not {}", row.len(), width)));
+                    }
+                    floors.last_mut().unwrap().push(row);
+                }
+                word => return Err(error(format!("unexpected {:?}", word))),
+            }
+        }
+
+        let end = text.lines().count();
+        let height = floors.first().map_or(0, |floor| floor.len() );
+        if height == 0 {
+            return Err(ParseError{line: end,  message: "no tiles".to_string()});
+        }
+        if let Some(floor) = floors.iter().position(|floor| floor.len() != height ) {
+            let message = format!("floor {} has {} rows, not {}", floor+1, floors[floor].len(), height);
+            return Err(ParseError{line: end,  message});
+        }
+        let tiles: Vec<Tile> = floors.into_iter()
+            .flat_map(|floor| floor.into_iter().flat_map(|row| row.into_iter() ) )
+            .collect();
+        if tiles.iter().filter(|&&tile| tile == Target ).count() > 1 {
+            return Err(ParseError{line: end,  message: "more than one target".to_string()});
+        }
+        let board = Board {
+            width: width.unwrap() as i32,
+            height: height as i32,
+            tiles,
+        };
+        for (line, drone) in &drones {
+            let pos = drone.pos;
+            if !pos.iter().all(|c| c.is_finite() ) {
+                // NaN would get through the checks below
+                return Err(ParseError{line: *line,  message: "drone position must be finite".to_string()});
+            }
+            if pos[2] != pos[2].floor()  ||  pos[0] < 0.0  ||  pos[1] < 0.0
+            || !board.contains(drone.tile()) {
+                return Err(ParseError{line: *line,  message: "drone is outside the board".to_string()});
+            }
+        }
+        for &(line, pos, _) in &spawner_kinds {
+            if board.get(pos) != Some(Spawner) {
+                return Err(ParseError{line,  message: "no spawner there".to_string()});
+            }
+        }
+        for (line, edit) in &edits {
+            let floors = edit.floors.0.max(edit.floors.1);
+            let outside = |[x,y,z]: [i32; 3]| x < 0  ||  x >= board.width  ||  y < 0  ||  y >= board.height
+                                            ||  z < 0  ||  z >= floors;
+            if edit.floors.0 < 1  ||  edit.floors.1 < 1
+            || edit.tiles.iter().any(|&(p, _, _)| outside(p) )
+            || edit.spawner_kinds.iter().any(|&(p, _, _)| outside(p) ) {
+                return Err(ParseError{line: *line,  message: "edit is outside the board".to_string()});
+            }
+        }
+        let drones = drones.into_iter().map(|(_, drone)| drone ).collect();
+        let spawner_kinds = spawner_kinds.into_iter().map(|(_, pos, kind)| (pos, kind) ).collect();
+        Ok(Scenario{seed, board, drones, spawner_kinds, settings, history})
+    }
+}
+
+impl fmt::Display for Scenario {
+    fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(seed) = self.seed {
+            writeln!(fmtr, "seed {}", seed)?;
+        }
+        for z in 0..self.board.floors() {
+            writeln!(fmtr, "floor")?;
+            for y in 0..self.board.height {
+                let row: String = (0..self.board.width).map(|x| tile_char(self.board[[x,y,z]]) ).collect();
+                writeln!(fmtr, "{}", row)?;
+            }
+        }
+        for drone in &self.drones {
+            let [x,y,z] = drone.pos;
+            writeln!(fmtr, "drone {} {} {} {}", x, y, z, drone.kind.name())?;
+        }
+        // spawners that have been replaced can be left behind
+        let mut spawners: Vec<_> = self.spawner_kinds.iter()
+            .filter(|&(&p, _)| self.board.get(p) == Some(Spawner) )
+            .collect();
+        spawners.sort_by_key(|&(p, _)| (p[2], p[1], p[0]) );
+        for (&[x,y,z], kind) in spawners {
+            writeln!(fmtr, "spawner {} {} {} {}", x, y, z, kind.name())?;
+        }
+        let settings = &self.settings;
+        writeln!(fmtr, "spawn_interval {}", settings.spawn_interval)?;
+        writeln!(fmtr, "spawn_burst {}", settings.spawn_burst)?;
+        match settings.spawn_cap {
+            Some(cap) => writeln!(fmtr, "spawn_cap {}", cap)?,
+            None => writeln!(fmtr, "spawn_cap none")?,
+        }
+        writeln!(fmtr, "max_drones {}", settings.max_drones)?;
+        match settings.battery {
+            Some(capacity) => writeln!(fmtr, "battery {}", capacity)?,
+            None => writeln!(fmtr, "battery none")?,
+        }
+        writeln!(fmtr, "low_battery {}", settings.low_battery)?;
+        writeln!(fmtr, "charge_rate {}", settings.charge_rate)?;
+        writeln!(fmtr, "on_arrival {}", settings.on_arrival.name())?;
+        writeln!(fmtr, "on_wall {}", settings.on_wall.name())?;
+        writeln!(fmtr, "movement {}", settings.movement.name())?;
+        writeln!(fmtr, "fallback {}", settings.fallback.name())?;
+        for edit in &self.history.undo {
+            write_edit(fmtr, "undo", edit)?;
+        }
+        for edit in self.history.redo.iter().rev() {
+            write_edit(fmtr, "redo", edit)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drones_must_be_on_the_board() {
+        let board = "floor\n...\n.T.\n";
+        assert!(Scenario::parse(&format!("{}drone 2.3 1.3 0\n", board)).is_ok());
+        for position in &["NaN 0.3 0", "0.3 nan 0", "inf 0.3 0", "0.3 0.3 NaN", "-0.5 0.3 0", "3.0 0.3 0", "0.3 0.3 0.5"] {
+            let error = Scenario::parse(&format!("{}drone {}\n", board, position)).unwrap_err();
+            assert_eq!(error.line, 4, "{}", position);
+        }
+    }
+}
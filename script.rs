@@ -0,0 +1,314 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+//! Scenarios written in [Rhai](https://rhai.rs).
+//!
+//! A script can read and change the board through these functions:
+//!
+//! ```text
+//! width(), height(), floors(), tick(), drones()
+//! tile(x, y, floor)             // "open", "wall", ..., or () outside the board
+//! set_tile(x, y, floor, name)   // walls follow the wall policy
+//! spawn_drone(x, y, floor)      // optionally followed by the kind of drone
+//! target()                      // [x, y, floor], or () if there is none
+//! move_target(x, y, floor)
+//! remove_target()
+//! distance(x, y, floor)         // to the target, optionally for a kind of drone,
+//!                               // or -1 if it cannot be reached
+//! ```
+//!
+//! Statements outside functions run when the script is loaded, followed by
+//! `fn on_start()` if it exists. `fn on_tick(tick)` runs after every tick.
+//! Both can keep state between calls in `this`, which starts as an empty map.
+//! Changes are made after the function returns, so `tile()` shows the board
+//! as it was when it was called.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::path::{Path,PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+use rhai::{Engine,AST,Scope,Dynamic,EvalAltResult,CallFnOptions,INT};
+use board::{Board,Tile};
+use board::Tile::*;
+use topology::FlowField;
+use kind::DroneKind;
+use sim::Simulation;
+
+
+#[derive(Clone, PartialEq,Eq, Debug)]
+pub struct ScriptError {
+    pub path: PathBuf,
+    pub message: String,
+} impl fmt::Display for ScriptError {
+    fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmtr, "{}: {}", self.path.display(), self.message)
+    }
+} impl Error for ScriptError {}
+
+
+enum Change {
+    Tile([i32; 3], Tile),
+    Spawn([i32; 3], DroneKind),
+    Target(Option<[i32; 3]>),
+}
+
+/// The parts of the simulation that scripts can read, which are moved in
+/// while a script runs, and the changes it makes.
+struct World {
+    board: Board,
+    paths: Vec<FlowField<[i32; 3]>>,
+    target: Option<[i32; 3]>,
+    ticks: u64,
+    drones: usize,
+    changes: Vec<Change>,
+} impl World {
+    fn swap(&mut self,  sim: &mut Simulation) {
+        mem::swap(&mut self.board, &mut sim.board);
+        mem::swap(&mut self.paths, &mut sim.paths);
+        self.target = sim.target;
+        self.ticks = sim.ticks;
+        self.drones = sim.drones.len();
+    }
+    fn position(&self,  x: INT,  y: INT,  floor: INT) -> Result<[i32; 3], Box<EvalAltResult>> {
+        let pos = [x as i32, y as i32, floor as i32];
+        if [x, y, floor] == [pos[0] as INT, pos[1] as INT, pos[2] as INT]  &&  self.board.contains(pos) {
+            Ok(pos)
+        } else {
+            Err(format!("{} {} {} is outside the board", x, y, floor).into())
+        }
+    }
+}
+
+fn tile_from_name(name: &str) -> Result<Tile, Box<EvalAltResult>> {
+    Tile::ALL.iter().cloned().find(|tile| tile.name() == name )
+        .ok_or_else(|| format!("unknown tile {:?}", name).into() )
+}
+fn kind_from_name(name: &str) -> Result<DroneKind, Box<EvalAltResult>> {
+    DroneKind::ALL.iter().cloned().find(|kind| kind.name() == name )
+        .ok_or_else(|| format!("unknown drone kind {:?}", name).into() )
+}
+
+
+/// A loaded script, which is reloaded when its file changes.
+pub struct Script {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    /// `this` in `on_start()` and `on_tick()`
+    state: Dynamic,
+    /// Set when it fails, to not repeat the error every tick until it's reloaded.
+    failed: bool,
+    world: Rc<RefCell<World>>,
+} impl Script {
+    /// Reads and compiles the script, without running it.
+    pub fn load(path: &Path) -> Result<Script, ScriptError> {
+        let world = Rc::new(RefCell::new(World {
+            board: Board::new(0, 0, 0),
+            paths: Vec::new(),
+            target: None,
+            ticks: 0,
+            drones: 0,
+            changes: Vec::new(),
+        }));
+        let mut script = Script {
+            path: path.to_path_buf(),
+            modified: None,
+            engine: Script::engine(&world),
+            ast: AST::empty(),
+            scope: Scope::new(),
+            state: Dynamic::from_map(Default::default()),
+            failed: false,
+            world,
+        };
+        script.compile()?;
+        Ok(script)
+    }
+
+    fn error(&self,  message: String) -> ScriptError {
+        ScriptError{path: self.path.clone(),  message}
+    }
+
+    fn compile(&mut self) -> Result<(), ScriptError> {
+        self.modified = fs::metadata(&self.path).and_then(|m| m.modified() ).ok();
+        let text = fs::read_to_string(&self.path).map_err(|e| self.error(e.to_string()) )?;
+        self.ast = self.engine.compile(&text).map_err(|e| self.error(e.to_string()) )?;
+        Ok(())
+    }
+
+    fn engine(world: &Rc<RefCell<World>>) -> Engine {
+        let mut engine = Engine::new();
+        let w = world.clone();
+        engine.register_fn("width", move || w.borrow().board.width as INT );
+        let w = world.clone();
+        engine.register_fn("height", move || w.borrow().board.height as INT );
+        let w = world.clone();
+        engine.register_fn("floors", move || w.borrow().board.floors() as INT );
+        let w = world.clone();
+        engine.register_fn("tick", move || w.borrow().ticks as INT );
+        let w = world.clone();
+        engine.register_fn("drones", move || w.borrow().drones as INT );
+        let w = world.clone();
+        engine.register_fn("tile", move |x: INT, y: INT, floor: INT| {
+            let world = w.borrow();
+            match world.position(x, y, floor) {
+                Ok(pos) => Dynamic::from(world.board[pos].name().to_string()),
+                Err(_) => Dynamic::UNIT,
+            }
+        });
+        let w = world.clone();
+        engine.register_fn("set_tile", move |x: INT, y: INT, floor: INT, name: &str| {
+            let mut world = w.borrow_mut();
+            let change = Change::Tile(world.position(x, y, floor)?, tile_from_name(name)?);
+            world.changes.push(change);
+            Ok::<(), Box<EvalAltResult>>(())
+        });
+        let w = world.clone();
+        engine.register_fn("spawn_drone", move |x: INT, y: INT, floor: INT| {
+            let mut world = w.borrow_mut();
+            let change = Change::Spawn(world.position(x, y, floor)?, DroneKind::default());
+            world.changes.push(change);
+            Ok::<(), Box<EvalAltResult>>(())
+        });
+        let w = world.clone();
+        engine.register_fn("spawn_drone", move |x: INT, y: INT, floor: INT, kind: &str| {
+            let mut world = w.borrow_mut();
+            let change = Change::Spawn(world.position(x, y, floor)?, kind_from_name(kind)?);
+            world.changes.push(change);
+            Ok::<(), Box<EvalAltResult>>(())
+        });
+        let w = world.clone();
+        engine.register_fn("target", move || match w.borrow().target {
+            Some(pos) => Dynamic::from_array(pos.iter().map(|&c| Dynamic::from(c as INT) ).collect()),
+            None => Dynamic::UNIT,
+        });
+        let w = world.clone();
+        engine.register_fn("move_target", move |x: INT, y: INT, floor: INT| {
+            let mut world = w.borrow_mut();
+            let change = Change::Target(Some(world.position(x, y, floor)?));
+            world.changes.push(change);
+            Ok::<(), Box<EvalAltResult>>(())
+        });
+        let w = world.clone();
+        engine.register_fn("remove_target", move || w.borrow_mut().changes.push(Change::Target(None)) );
+        fn distance(world: &World,  x: INT,  y: INT,  floor: INT,  kind: DroneKind)
+        -> Result<INT, Box<EvalAltResult>> {
+            let pos = world.position(x, y, floor)?;
+            let field = DroneKind::ALL.iter().position(|&k| k == kind ).unwrap();
+            Ok(match world.paths.get(field).and_then(|path| path.get(&world.board, pos) ) {
+                Some(step) => step.distance as INT,
+                None => -1,
+            })
+        }
+        let w = world.clone();
+        engine.register_fn("distance", move |x: INT, y: INT, floor: INT| {
+            distance(&w.borrow(), x, y, floor, DroneKind::default())
+        });
+        let w = world.clone();
+        engine.register_fn("distance", move |x: INT, y: INT, floor: INT, kind: &str| {
+            distance(&w.borrow(), x, y, floor, kind_from_name(kind)?)
+        });
+        engine
+    }
+
+    fn has_fn(&self,  name: &str,  params: usize) -> bool {
+        self.ast.iter_functions().any(|f| f.name == name  &&  f.params.len() == params )
+    }
+
+    /// Runs `run` with the simulation moved into the world, and then makes
+    /// the changes the script asked for.
+    fn with_world<F>(&mut self,  sim: &mut Simulation,  run: F) -> Result<(), ScriptError>
+    where F: FnOnce(&mut Script) -> Result<(), Box<EvalAltResult>> {
+        self.world.borrow_mut().swap(sim);
+        let result = run(self);
+        self.world.borrow_mut().swap(sim);
+        self.failed = result.is_err();
+        let changes = mem::take(&mut self.world.borrow_mut().changes);
+        // recomputing paths is slow, so only do it once
+        let mut edited = false;
+        for change in changes {
+            match change {
+                Change::Tile(pos, Wall) => sim.place_walls(&[pos]),
+                Change::Tile(pos, Target) => sim.place_target(Some(pos)),
+                Change::Tile(pos, tile) => {
+                    if sim.target == Some(pos) {
+                        sim.place_target(None);
+                    }
+                    sim.set_tile(pos, tile);
+                }
+                Change::Spawn(pos, kind) => {
+                    sim.spawn_drone(pos, kind);
+                    continue;
+                }
+                Change::Target(to) => sim.place_target(to),
+            }
+            edited = true;
+        }
+        if edited {
+            sim.update_paths();
+        }
+        result.map_err(|e| self.error(e.to_string()) )
+    }
+
+    /// Runs the statements outside functions, and then `on_start()`.
+    pub fn start(&mut self,  sim: &mut Simulation) -> Result<(), ScriptError> {
+        self.scope = Scope::new();
+        self.state = Dynamic::from_map(Default::default());
+        self.with_world(sim, |script| {
+            script.engine.run_ast_with_scope(&mut script.scope, &script.ast)?;
+            if script.has_fn("on_start", 0) {
+                let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut script.state);
+                let _ = script.engine.call_fn_with_options::<Dynamic>(options, &mut script.scope, &script.ast, "on_start", ())?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Runs `on_tick()`, if the script has it and hasn't failed.
+    pub fn tick(&mut self,  sim: &mut Simulation) -> Result<(), ScriptError> {
+        if self.failed  ||  !self.has_fn("on_tick", 1) {
+            return Ok(());
+        }
+        let tick = sim.ticks as INT;
+        self.with_world(sim, |script| {
+            let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut script.state);
+            let _ = script.engine.call_fn_with_options::<Dynamic>(options, &mut script.scope, &script.ast, "on_tick", (tick,))?;
+            Ok(())
+        })
+    }
+
+    /// Loads and starts the script again if its file has been modified.
+    /// If it no longer compiles, the old version keeps running.
+    pub fn reload_if_changed(&mut self,  sim: &mut Simulation) -> Result<bool, ScriptError> {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified() ).ok();
+        if modified.is_none()  ||  modified == self.modified {
+            return Ok(false);
+        }
+        let old = mem::replace(&mut self.ast, AST::empty());
+        if let Err(e) = self.compile() {
+            self.ast = old;
+            return Err(e);
+        }
+        self.start(sim)?;
+        Ok(true)
+    }
+}
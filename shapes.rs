@@ -0,0 +1,190 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+//! The tiles that editing tools change.
+//!
+//! Positions outside the board are included, so callers must skip them.
+
+use board::Board;
+use topology::Topology;
+
+/// A square of `size` by `size` tiles around `center`, on its floor.
+/// Even sizes extend further right and down.
+pub fn brush(center: [i32; 3],  size: i32) -> Vec<[i32; 3]> {
+    let size = size.max(1);
+    let first = [center[0] - (size-1)/2,  center[1] - (size-1)/2];
+    let mut tiles = Vec::with_capacity((size*size) as usize);
+    for y in first[1]..first[1]+size {
+        for x in first[0]..first[0]+size {
+            tiles.push([x, y, center[2]]);
+        }
+    }
+    tiles
+}
+
+/// The tiles on a straight line between `from` and `to` (Bresenham's
+/// algorithm), including both, on the floor of `from`.
+/// Swapping `from` and `to` gives the same tiles in reverse order.
+pub fn line(from: [i32; 3],  to: [i32; 3]) -> Vec<[i32; 3]> {
+    if [to[1], to[0]] < [from[1], from[0]] {
+        // ties are broken differently in the other direction
+        let mut tiles = line([to[0], to[1], from[2]],  from);
+        tiles.reverse();
+        return tiles;
+    }
+    let (dx, dy) = ((to[0]-from[0]).abs(),  -(to[1]-from[1]).abs());
+    let (sx, sy) = ((to[0]-from[0]).signum(),  (to[1]-from[1]).signum());
+    let mut error = dx + dy;
+    let [mut x, mut y, z] = from;
+    let mut tiles = vec![[x, y, z]];
+    while [x, y] != [to[0], to[1]] {
+        let e2 = 2*error;
+        if e2 >= dy {
+            error += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            error += dx;
+            y += sy;
+        }
+        tiles.push([x, y, z]);
+    }
+    tiles
+}
+
+/// The border of the rectangle with `a` and `b` as opposite corners,
+/// on the floor of `a`.
+pub fn hollow_rectangle(a: [i32; 3],  b: [i32; 3]) -> Vec<[i32; 3]> {
+    let (x0, x1) = (a[0].min(b[0]), a[0].max(b[0]));
+    let (y0, y1) = (a[1].min(b[1]), a[1].max(b[1]));
+    let mut tiles = Vec::new();
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            if x == x0  ||  x == x1  ||  y == y0  ||  y == y1 {
+                tiles.push([x, y, a[2]]);
+            }
+        }
+    }
+    tiles
+}
+
+/// The outline of the ellipse that fits inside the rectangle with `a` and
+/// `b` as opposite corners, on the floor of `a`.
+pub fn ellipse(a: [i32; 3],  b: [i32; 3]) -> Vec<[i32; 3]> {
+    let (x0, x1) = (a[0].min(b[0]), a[0].max(b[0]));
+    let (y0, y1) = (a[1].min(b[1]), a[1].max(b[1]));
+    let center = [(x0 + x1 + 1) as f64 / 2.0,  (y0 + y1 + 1) as f64 / 2.0];
+    let radius = [(x1 - x0 + 1) as f64 / 2.0,  (y1 - y0 + 1) as f64 / 2.0];
+    let inside = |x: i32,  y: i32| {
+        let dx = (x as f64 + 0.5 - center[0]) / radius[0];
+        let dy = (y as f64 + 0.5 - center[1]) / radius[1];
+        dx*dx + dy*dy <= 1.0
+    };
+    let mut tiles = Vec::new();
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            // inside, but next to a tile that isn't
+            if inside(x, y)
+            && !(inside(x-1, y)  &&  inside(x+1, y)  &&  inside(x, y-1)  &&  inside(x, y+1)) {
+                tiles.push([x, y, a[2]]);
+            }
+        }
+    }
+    tiles
+}
+
+/// The tiles of the same type as `start` that can be reached from it
+/// without changing floor or moving diagonally, including `start`.
+pub fn flood_fill(board: &Board,  start: [i32; 3]) -> Vec<[i32; 3]> {
+    let tile = match board.get(start) {
+        Some(tile) => tile,
+        None => return Vec::new(),
+    };
+    let mut reached = vec![false; board.tiles.len()];
+    reached[board.index(start)] = true;
+    let mut tiles = vec![start];
+    let mut i = 0;
+    while i < tiles.len() {
+        let [x, y, z] = tiles[i];
+        for next in [[x-1, y, z], [x+1, y, z], [x, y-1, z], [x, y+1, z]] {
+            if board.get(next) == Some(tile)  &&  !reached[board.index(next)] {
+                reached[board.index(next)] = true;
+                tiles.push(next);
+            }
+        }
+        i += 1;
+    }
+    tiles
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use board::Tile::*;
+
+    #[test]
+    fn lines_are_connected_and_symmetric() {
+        for x in -7..=7 {
+            for y in -7..=7 {
+                let (from, to) = ([2,3,1], [2+x, 3+y, 1]);
+                let tiles = line(from, to);
+                assert_eq!(tiles[0], from);
+                assert_eq!(*tiles.last().unwrap(), to);
+                assert_eq!(tiles.len() as i32, x.abs().max(y.abs()) + 1, "to {:?}", to);
+                assert!(tiles.windows(2).all(|w| (w[0][0]-w[1][0]).abs() <= 1  &&  (w[0][1]-w[1][1]).abs() <= 1 ));
+                let mut back = line(to, from);
+                back.reverse();
+                assert_eq!(back, tiles, "to {:?}", to);
+            }
+        }
+        assert_eq!(line([1,1,0], [1,1,0]), vec![[1,1,0]]);
+        assert_eq!(line([0,0,2], [3,0,5]), vec![[0,0,2], [1,0,2], [2,0,2], [3,0,2]]);
+    }
+
+    #[test]
+    fn narrow_ellipses_are_filled() {
+        for n in 1..8 {
+            let mut column = ellipse([4,2,0], [4,2+n-1,0]);
+            column.sort();
+            assert_eq!(column, (2..2+n).map(|y| [4,y,0] ).collect::<Vec<_>>());
+            let mut row = ellipse([4+n-1,2,0], [4,2,0]);
+            row.sort();
+            assert_eq!(row, (4..4+n).map(|x| [x,2,0] ).collect::<Vec<_>>());
+        }
+        // two wide is also only border
+        assert_eq!(ellipse([0,0,0], [1,5,0]).len(), 12);
+    }
+
+    #[test]
+    fn flood_fill_stays_within_the_tile_type_and_floor() {
+        // the open area is split by a wall of mud
+        let mut board = Board::new(5, 3, 2);
+        for y in 0..3 {
+            board[[2,y,0]] = Mud;
+        }
+        let mut left = flood_fill(&board, [0,1,0]);
+        let mut expected: Vec<_> = Board::positions([0,0,0], [1,2,0]).collect();
+        left.sort();
+        expected.sort();
+        assert_eq!(left, expected);
+        assert_eq!(flood_fill(&board, [2,0,0]).len(), 3);
+        // all of the other floor, but nothing below it
+        assert_eq!(flood_fill(&board, [4,2,1]).len(), 15);
+        assert_eq!(flood_fill(&board, [5,0,0]), Vec::<[i32; 3]>::new());
+    }
+}
@@ -0,0 +1,949 @@
+/* Copyright (C) 2015 Alexandru Cojocaru,
+ *               2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Moving the drones, one tile per tick, and creating and removing them.
+//!
+//! All randomness comes from the seed, so two simulations created with the
+//! same seed and given the same edits between the same ticks end up with
+//! bit-identical drones. Randomness for moving a drone only depends on the
+//! seed, tick and drone, so it doesn't matter which order or how many threads
+//! drones are moved in.
+
+use std::collections::{HashMap,HashSet,VecDeque};
+use std::mem;
+use std::rc::Rc;
+use rand::{Rng,SeedableRng};
+use rand::rngs::SmallRng;
+use rand::distributions::Open01;
+use vecmath::{vec2_add,vec3_add,vec3_sub}; // Vector2 is [T; 2]
+// Why not just use `+`?
+// Any math library in rust have to make a choice: Either use primitive slices or tuples
+// which make constructing and destructuring pain-free, or use std::ops::*.
+// Rusts coherence rules prevents them for doing both:
+// You cannot implement an external trait for a foreign type.
+use board::{Board,Tile};
+use board::Tile::*;
+use topology::{self,Topology,FlowField};
+use scenario::Scenario;
+use steering::{self,SteeringSettings};
+use stats::Stats;
+use kind::DroneKind;
+use events::{Event,Subscriber};
+use drones::{Drone,Drones,DronesMut,tile_of,drone_rng};
+use fallback::{self,Fallback,Fallbacks};
+
+
+/// What happens to a drone when it reaches the target.
+#[derive(Clone,Copy, PartialEq,Eq, Debug)]
+pub enum Arrival {
+    /// It's removed.
+    Despawn,
+    /// It stays, and another drone is created at the target every tick.
+    Clone,
+    /// It's moved to a random spawner, or stays if there are none.
+    Respawn,
+    /// It stays.
+    Idle,
+}
+impl Arrival {
+    pub const ALL: [Arrival; 4] = [Arrival::Despawn, Arrival::Clone, Arrival::Respawn, Arrival::Idle];
+    pub fn name(self) -> &'static str {
+        match self {
+            Arrival::Despawn => "despawn",
+            Arrival::Clone => "clone",
+            Arrival::Respawn => "respawn",
+            Arrival::Idle => "idle",
+        }
+    }
+}
+
+/// What happens to drones on tiles that walls are placed on.
+#[derive(Clone,Copy, PartialEq,Eq, Debug)]
+pub enum WallPolicy {
+    /// They're removed.
+    Destroy,
+    /// They're moved to the nearest tile that isn't a wall.
+    Relocate,
+    /// No wall is placed on tiles with drones.
+    Refuse,
+}
+impl WallPolicy {
+    pub const ALL: [WallPolicy; 3] = [WallPolicy::Destroy, WallPolicy::Relocate, WallPolicy::Refuse];
+    pub fn name(self) -> &'static str {
+        match self {
+            WallPolicy::Destroy => "destroy",
+            WallPolicy::Relocate => "relocate",
+            WallPolicy::Refuse => "refuse",
+        }
+    }
+}
+
+/// How drones move.
+#[derive(Clone,Copy, PartialEq,Eq, Debug)]
+pub enum Movement {
+    /// One tile per tick, and drones pass through each other.
+    Classic,
+    /// With velocity and acceleration, see the `steering` module.
+    Steering,
+}
+impl Movement {
+    pub const ALL: [Movement; 2] = [Movement::Classic, Movement::Steering];
+    pub fn name(self) -> &'static str {
+        match self {
+            Movement::Classic => "classic",
+            Movement::Steering => "steering",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Settings {
+    /// Spawners create drones every this many ticks.
+    pub spawn_interval: u32,
+    /// How many drones each spawner creates at a time.
+    pub spawn_burst: u32,
+    /// How many drones each spawner creates in total, or `None` for no limit.
+    pub spawn_cap: Option<u32>,
+    pub on_arrival: Arrival,
+    pub on_wall: WallPolicy,
+    /// No drones are created or cloned while there are this many.
+    pub max_drones: usize,
+    /// How many tiles drones can move on a full battery, or `None` for no limit.
+    pub battery: Option<u32>,
+    /// Drones with less energy than this go to the nearest charger.
+    pub low_battery: u32,
+    /// How much energy drones get per tick on a charger.
+    pub charge_rate: u32,
+    pub movement: Movement,
+    /// Only used with `Movement::Steering`
+    pub steering: SteeringSettings,
+    /// What drones that cannot reach the target do.
+    /// Call `Simulation::update_paths()` after changing it.
+    pub fallback: Fallback,
+}
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            spawn_interval: 10,
+            spawn_burst: 1,
+            spawn_cap: Some(10),
+            on_arrival: Arrival::Clone,
+            on_wall: WallPolicy::Destroy,
+            max_drones: 10_000,
+            battery: None,
+            low_battery: 20,
+            charge_rate: 5,
+            movement: Movement::Classic,
+            steering: SteeringSettings::default(),
+            fallback: Fallback::default(),
+        }
+    }
+}
+
+
+/// A drone in a snapshot, without `Drone::prev` which the next tick overwrites.
+#[derive(Clone,Copy, PartialEq, Debug)]
+struct StoredDrone {
+    id: u64,
+    pos: [f64; 3],
+    vel: [f64; 2],
+    born: u64,
+    steps: u32,
+    energy: u32,
+    /// `u32::MAX` for none
+    order: u32,
+    kind: DroneKind,
+    /// `ARRIVED`, `CHARGING` and `TRAPPED`
+    flags: u8,
+}
+const ARRIVED: u8 = 1;
+const CHARGING: u8 = 2;
+const TRAPPED: u8 = 4;
+impl StoredDrone {
+    fn new(d: &Drones,  i: usize) -> StoredDrone {
+        StoredDrone {
+            id: d.id[i],  pos: d.pos[i],  vel: d.vel[i],  born: d.born[i],
+            steps: d.steps[i],  energy: d.energy[i],
+            order: d.order[i].unwrap_or(u32::MAX),
+            kind: d.kind[i],
+            flags: (d.arrived[i] as u8 * ARRIVED)  |  (d.charging[i] as u8 * CHARGING)  |  (d.trapped[i] as u8 * TRAPPED),
+        }
+    }
+    fn drone(&self) -> Drone {
+        Drone {
+            id: self.id,  pos: self.pos,  prev: self.pos,  vel: self.vel,  born: self.born,
+            steps: self.steps,  energy: self.energy,
+            order: if self.order == u32::MAX {None} else {Some(self.order)},
+            kind: self.kind,
+            arrived: self.flags & ARRIVED != 0,
+            charging: self.flags & CHARGING != 0,
+            trapped: self.flags & TRAPPED != 0,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum StoredDrones {
+    All(Vec<StoredDrone>),
+    /// The number of drones, and those that are different from the
+    /// previous snapshot with their index.
+    Changed(usize, Vec<(usize, StoredDrone)>),
+}
+
+/// The state of a simulation at one tick, without anything that can be
+/// recomputed from it. Settings and subscribers are not included.
+///
+/// To take less space, snapshots share the board with the previous one if
+/// it hasn't changed, and can store only the drones that changed since the
+/// previous snapshot, see `changes_since()`.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    board: Rc<Board>,
+    target: Option<[i32; 3]>,
+    drones: StoredDrones,
+    spawner_kinds: HashMap<[i32; 3], DroneKind>,
+    ticks: u64,
+    stats: Stats,
+    spawned: HashMap<[i32; 3], u32>,
+    next_id: u64,
+    rng: SmallRng,
+    visited: Rc<Vec<u64>>,
+    destinations: Vec<[i32; 3]>,
+} impl Snapshot {
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+    /// How many drones are stored, which is less than the number of drones
+    /// if only changes are.
+    pub fn stored_drones(&self) -> usize {
+        match &self.drones {
+            StoredDrones::All(drones) => drones.len(),
+            StoredDrones::Changed(_, changed) => changed.len(),
+        }
+    }
+    /// Whether it has all drones and not only changes, so that it can be restored.
+    pub fn is_complete(&self) -> bool {
+        matches!(self.drones, StoredDrones::All(_))
+    }
+    /// Returns a snapshot that only stores the drones that are different in
+    /// this one than in `previous`. Both must be complete.
+    pub fn changes_since(&self,  previous: &Snapshot) -> Snapshot {
+        let (now, before) = match (&self.drones, &previous.drones) {
+            (StoredDrones::All(now), StoredDrones::All(before)) => (now, before),
+            _ => panic!("changes between incomplete snapshots"),
+        };
+        let changed = now.iter().enumerate()
+            .filter(|&(i, drone)| before.get(i) != Some(drone) )
+            .map(|(i, &drone)| (i, drone) )
+            .collect();
+        Snapshot{drones: StoredDrones::Changed(now.len(), changed),  ..self.clone_without_drones()}
+    }
+    /// Turns a complete snapshot into the next one, which can be incomplete.
+    pub fn apply(&mut self,  next: &Snapshot) {
+        let mut drones = match mem::replace(&mut self.drones, StoredDrones::All(Vec::new())) {
+            StoredDrones::All(drones) => drones,
+            StoredDrones::Changed(..) => panic!("applying to an incomplete snapshot"),
+        };
+        match &next.drones {
+            StoredDrones::All(all) => drones.clone_from(all),
+            StoredDrones::Changed(len, changed) => {
+                drones.truncate(*len);
+                for &(i, drone) in changed {
+                    if i < drones.len() {
+                        drones[i] = drone;
+                    } else {
+                        drones.push(drone); // added in index order
+                    }
+                }
+            }
+        }
+        *self = Snapshot{drones: StoredDrones::All(drones),  ..next.clone_without_drones()};
+    }
+    fn clone_without_drones(&self) -> Snapshot {
+        Snapshot {
+            board: self.board.clone(),
+            target: self.target,
+            drones: StoredDrones::Changed(0, Vec::new()),
+            spawner_kinds: self.spawner_kinds.clone(),
+            ticks: self.ticks,
+            stats: self.stats.clone(),
+            spawned: self.spawned.clone(),
+            next_id: self.next_id,
+            rng: self.rng.clone(),
+            visited: self.visited.clone(),
+            destinations: self.destinations.clone(),
+        }
+    }
+}
+
+/// Snapshots are equal when restoring them gives the same simulation,
+/// but a complete snapshot is never equal to one with only changes.
+impl PartialEq for Snapshot {
+    fn eq(&self,  other: &Snapshot) -> bool {
+        // SmallRng cannot be compared, but equal states give equal numbers
+        let (mut a, mut b) = (self.rng.clone(), other.rng.clone());
+        self.board == other.board  &&  self.target == other.target
+        && self.drones == other.drones  &&  self.spawner_kinds == other.spawner_kinds
+        && self.ticks == other.ticks  &&  self.stats == other.stats
+        && self.spawned == other.spawned  &&  self.next_id == other.next_id
+        && self.visited == other.visited  &&  self.destinations == other.destinations
+        && (0..4).all(|_| a.gen::<u64>() == b.gen::<u64>() )
+    }
+}
+
+
+pub struct Simulation {
+    pub board: Board,
+    /// One to the target for each of `DroneKind::ALL`, followed by one to
+    /// the nearest charger for each, and then one for each kind to each of
+    /// `destinations`. See `paths()` and `Drone::field()`.
+    pub paths: Vec<FlowField<[i32; 3]>>,
+    /// With `Fallback::Closest`, one for each of `DroneKind::ALL` to the
+    /// tile closest to the target in every part of the board it cannot
+    /// reach the target from. Otherwise empty.
+    pub closest: Vec<FlowField<[i32; 3]>>,
+    pub drones: Drones,
+    /// The kind of drones each spawner creates, if not the default.
+    pub spawner_kinds: HashMap<[i32; 3], DroneKind>,
+    pub target: Option<[i32; 3]>,
+    pub settings: Settings,
+    /// The number of ticks so far.
+    pub ticks: u64,
+    pub stats: Stats,
+    /// How many drones each spawner has created.
+    spawned: HashMap<[i32; 3], u32>,
+    /// The id of the next drone created.
+    next_id: u64,
+    subscribers: Vec<Box<dyn Subscriber>>,
+    seed: u64,
+    rng: SmallRng,
+    /// The last tick each tile had a drone in it, for `Fallback::Explore`.
+    visited: Vec<u64>,
+    /// Where drones have been sent with `send_drones()`, see `Drone::order`.
+    destinations: Vec<[i32; 3]>,
+} impl Simulation {
+    pub fn new(scenario: Scenario,  seed: u64) -> Simulation {
+        let target = scenario.board.tiles.iter()
+            .position(|&tile| tile == Target )
+            .map(|i| scenario.board.position(i) );
+        let mut sim = Simulation {
+            paths: Vec::new(),
+            closest: Vec::new(),
+            visited: Vec::new(),
+            destinations: Vec::new(),
+            board: scenario.board,
+            next_id: scenario.drones.len() as u64,
+            drones: scenario.drones.into_iter().enumerate()
+                .map(|(id, drone)| Drone{id: id as u64,  ..drone} )
+                .collect(),
+            subscribers: Vec::new(),
+            spawner_kinds: scenario.spawner_kinds,
+            target,
+            settings: scenario.settings,
+            ticks: 0,
+            stats: Stats::default(),
+            spawned: HashMap::new(),
+            seed,
+            rng: SmallRng::seed_from_u64(seed),
+        };
+        sim.update_paths();
+        sim
+    }
+
+    /// Takes a complete snapshot.
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot_sharing(None)
+    }
+    /// Takes a complete snapshot, that shares the board and other large
+    /// parts with `previous` if they haven't changed since it.
+    pub fn snapshot_sharing(&self,  previous: Option<&Snapshot>) -> Snapshot {
+        let board = match previous {
+            Some(p) if (p.board.width, p.board.height) == (self.board.width, self.board.height)
+                    &&  p.board.tiles == self.board.tiles => p.board.clone(),
+            _ => Rc::new(self.board.clone()),
+        };
+        let visited = match previous {
+            Some(p) if *p.visited == self.visited => p.visited.clone(),
+            _ => Rc::new(self.visited.clone()),
+        };
+        Snapshot {
+            board,
+            visited,
+            target: self.target,
+            drones: StoredDrones::All((0..self.drones.len()).map(|i| StoredDrone::new(&self.drones, i) ).collect()),
+            spawner_kinds: self.spawner_kinds.clone(),
+            ticks: self.ticks,
+            stats: self.stats.clone(),
+            spawned: self.spawned.clone(),
+            next_id: self.next_id,
+            rng: self.rng.clone(),
+            destinations: self.destinations.clone(),
+        }
+    }
+    /// Goes back or forward to when `snapshot` was taken, which must be complete.
+    ///
+    /// Drones that are at the same index as before move from where they were,
+    /// so that replaying one snapshot after another looks like ticking.
+    pub fn restore(&mut self,  snapshot: &Snapshot) {
+        let stored = match &snapshot.drones {
+            StoredDrones::All(drones) => drones,
+            StoredDrones::Changed(..) => panic!("restoring an incomplete snapshot"),
+        };
+        let drones: Drones = stored.iter().enumerate()
+            .map(|(i, stored)| match self.drones.id.get(i) {
+                Some(&id) if id == stored.id => Drone{prev: self.drones.pos[i],  ..stored.drone()},
+                _ => stored.drone(),
+            })
+            .collect();
+        self.drones = drones;
+        self.board = (*snapshot.board).clone();
+        self.target = snapshot.target;
+        self.spawner_kinds = snapshot.spawner_kinds.clone();
+        self.ticks = snapshot.ticks;
+        self.stats = snapshot.stats.clone();
+        self.spawned = snapshot.spawned.clone();
+        self.next_id = snapshot.next_id;
+        self.rng = snapshot.rng.clone();
+        self.visited.clone_from(&snapshot.visited);
+        self.destinations.clone_from(&snapshot.destinations);
+        self.update_paths();
+    }
+
+    /// Calls `subscriber` with every event from now on.
+    pub fn subscribe<S: Subscriber + 'static>(&mut self,  subscriber: S) {
+        self.subscribers.push(Box::new(subscriber));
+    }
+    fn emit(&mut self,  event: Event) {
+        for subscriber in &mut self.subscribers {
+            subscriber.event(self.ticks, &event);
+        }
+    }
+
+    /// Gives the drone an id and adds it.
+    fn add_drone(&mut self,  drone: Drone) {
+        let drone = Drone{id: self.next_id,  born: self.ticks,  ..drone};
+        self.next_id += 1;
+        self.drones.push(drone);
+        self.emit(Event::Spawned{drone: drone.id,  pos: drone.tile()});
+    }
+
+    /// The seed the simulation was created with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn tick(&mut self) {
+        self.ticks += 1;
+
+        let mut accelerations = match self.settings.movement {
+            Movement::Classic => Vec::new(),
+            Movement::Steering => steering::accelerations(&self.board, &self.paths, &self.fallbacks(),
+                                                          &self.drones, &self.settings.steering,
+                                                          self.seed, self.ticks),
+        };
+
+        // First handle the drones that might create or remove drones, one at a time.
+        let spawners = match self.settings.on_arrival {
+            Arrival::Respawn => self.spawners(),
+            _ => Vec::new(),
+        };
+        let mut i = 0;
+        let mut len = self.drones.len();// Don't increase when I add new
+        while i < len {
+            self.drones.prev[i] = self.drones.pos[i];
+            let pos = self.drones.tile(i);
+            let kind = self.drones.kind[i];
+            let mut remove = false;
+            match self.board[pos] {
+                Target => {
+                    if !self.drones.arrived[i] {
+                        self.drones.arrived[i] = true;
+                        let (id, born, steps) = (self.drones.id[i], self.drones.born[i], self.drones.steps[i]);
+                        self.stats.arrival(self.ticks, self.ticks - born, steps);
+                        self.emit(Event::Arrived{drone: id,  pos});
+                    }
+                    match self.settings.on_arrival {
+                        Arrival::Clone if self.drones.len() < self.settings.max_drones => {
+                            // born where it arrives, so not counted again
+                            let m = self.drones.pos[i];
+                            self.add_drone(Drone{arrived: true,  kind,  ..Drone::new(m)});
+                        },
+                        Arrival::Despawn => remove = true,
+                        Arrival::Respawn => if let Some(spawner) = self.random_spawner(&spawners) {
+                            let pos = Simulation::spawn_position(spawner);
+                            let id = self.drones.id[i];
+                            self.drones.set(i, Drone{id,  born: self.ticks,  kind,  ..Drone::new(pos)});
+                            self.emit(Event::Moved{drone: id,  to: spawner});
+                        },
+                        Arrival::Clone | Arrival::Idle => {},
+                    }
+                },
+                Wall if !kind.can_enter(Wall) => {
+                    remove = true;
+                    self.stats.destroyed += 1;
+                    let id = self.drones.id[i];
+                    self.emit(Event::Destroyed{drone: id,  pos});
+                },
+                _ => {},
+            }
+            if remove {
+                // Move the last drone that hasn't been updated yet here,
+                // and clones from this tick stay after it.
+                len -= 1;
+                self.drones.swap(i, len);
+                self.drones.swap_remove(len);
+                if !accelerations.is_empty() {
+                    accelerations.swap(i, len);
+                }
+                i = i.wrapping_sub(1);
+            }
+            i = i.wrapping_add(1);
+        }
+
+        // Then move the others, which only changes each drone itself.
+        {
+            let mover = Mover {
+                board: &self.board,
+                paths: &self.paths,
+                settings: &self.settings,
+                accelerations: &accelerations,
+                fallbacks: Fallbacks {
+                    fallback: self.settings.fallback,
+                    closest: &self.closest,
+                    visited: &self.visited,
+                },
+                seed: self.seed,
+                ticks: self.ticks,
+            };
+            self.drones.for_each_chunk(len, |chunk| mover.move_drones(chunk) );
+        }
+        if self.settings.fallback == Fallback::Explore {
+            for i in 0..self.drones.len() {
+                let t = self.board.index(self.drones.tile(i));
+                self.visited[t] = self.ticks;
+            }
+        }
+        if !self.subscribers.is_empty() {
+            for i in 0..len {
+                let tile = self.drones.tile(i);
+                if tile != tile_of(self.drones.prev[i]) {
+                    let id = self.drones.id[i];
+                    self.emit(Event::Moved{drone: id,  to: tile});
+                }
+            }
+        }
+
+        if self.ticks.is_multiple_of(self.settings.spawn_interval.max(1) as u64) {
+            self.spawn();
+        }
+        self.count_trapped();
+    }
+
+    fn fallbacks(&self) -> Fallbacks<'_> {
+        Fallbacks {
+            fallback: self.settings.fallback,
+            closest: &self.closest,
+            visited: &self.visited,
+        }
+    }
+
+    /// The flow field to the target for a kind of drone.
+    pub fn paths(&self,  kind: DroneKind) -> &FlowField<[i32; 3]> {
+        &self.paths[kind as usize]
+    }
+
+    /// Center the drone in the tile
+    fn spawn_position(tile: [i32; 3]) -> [f64; 3] {
+        [tile[0] as f64+0.3,  tile[1] as f64+0.3,  tile[2] as f64]
+    }
+    fn spawners(&self) -> Vec<[i32; 3]> {
+        self.board.tiles.iter().enumerate()
+            .filter(|&(_, &tile)| tile == Spawner )
+            .map(|(i, _)| self.board.position(i) )
+            .collect()
+    }
+    fn random_spawner(&mut self,  spawners: &[[i32; 3]]) -> Option<[i32; 3]> {
+        if spawners.is_empty() {
+            None
+        } else {
+            Some(spawners[self.rng.gen_range(0, spawners.len())])
+        }
+    }
+    fn spawn(&mut self) {
+        for spawner in self.spawners() {
+            for _ in 0..self.settings.spawn_burst {
+                let spawned = self.spawned.entry(spawner).or_insert(0);
+                if self.settings.spawn_cap.is_some_and(|cap| *spawned >= cap )
+                || self.drones.len() >= self.settings.max_drones {
+                    break;
+                }
+                *spawned += 1;
+                let kind = self.spawner_kinds.get(&spawner).cloned().unwrap_or_default();
+                self.stats.spawned += 1;
+                self.add_drone(Drone{kind,  ..Drone::new(Simulation::spawn_position(spawner))});
+            }
+        }
+    }
+    /// Creates a drone of `kind` on `tile` for the user or a script,
+    /// unless there are `max_drones` already.
+    pub fn spawn_drone(&mut self,  tile: [i32; 3],  kind: DroneKind) -> bool {
+        if self.at_max_drones() {
+            return false;
+        }
+        self.stats.placed += 1;
+        self.add_drone(Drone{kind,  ..Drone::new(Simulation::spawn_position(tile))});
+        true
+    }
+
+    /// Whether the `max_drones` setting prevents creating more drones.
+    pub fn at_max_drones(&self) -> bool {
+        self.drones.len() >= self.settings.max_drones
+    }
+
+    /// Sends the drones with the ids to `to` instead of the target,
+    /// where they stay until sent somewhere else.
+    pub fn send_drones(&mut self,  ids: &HashSet<u64>,  to: [i32; 3]) {
+        let order = match self.destinations.iter().position(|&d| d == to ) {
+            Some(existing) => existing,
+            None => {
+                self.destinations.push(to);
+                self.destinations.len()-1
+            }
+        };
+        for (id, drone_order) in self.drones.id.iter().zip(&mut self.drones.order) {
+            if ids.contains(id) {
+                *drone_order = Some(order as u32);
+            }
+        }
+        self.update_paths();
+    }
+    /// Where `Drone::order` sends a drone.
+    pub fn destination(&self,  order: u32) -> [i32; 3] {
+        self.destinations[order as usize]
+    }
+
+    /// Removes the drones with the ids.
+    pub fn remove_drones(&mut self,  ids: &HashSet<u64>) {
+        let mut i = 0;
+        while i < self.drones.len() {
+            if ids.contains(&self.drones.id[i]) {
+                let drone = self.drones.swap_remove(i);
+                self.stats.removed += 1;
+                self.emit(Event::Destroyed{drone: drone.id,  pos: drone.tile()});
+            } else {
+                i += 1;
+            }
+        }
+        self.update_paths();
+    }
+
+    /// Cancels orders to destinations outside the board, and removes
+    /// destinations no drone is sent to.
+    fn forget_destinations(&mut self) {
+        let mut used = vec![false; self.destinations.len()];
+        for order in &mut self.drones.order {
+            if let Some(o) = *order {
+                if self.board.contains(self.destinations[o as usize]) {
+                    used[o as usize] = true;
+                } else {
+                    *order = None;
+                }
+            }
+        }
+        let mut renumbered = Vec::with_capacity(used.len());
+        let mut kept = 0;
+        for &used in &used {
+            renumbered.push(kept);
+            kept += used as u32;
+        }
+        let mut o = 0;
+        self.destinations.retain(|_| {
+            o += 1;
+            used[o-1]
+        });
+        for order in self.drones.order.iter_mut().flatten() {
+            *order = renumbered[*order as usize];
+        }
+    }
+
+    /// Recalculates the numbers when the destination you change the destination.
+    /// Must be called after changing tiles.
+    pub fn update_paths(&mut self) {
+        self.forget_destinations();
+        let board = &self.board;
+        let targets: Vec<[i32; 3]> = self.target.into_iter().collect();
+        let chargers: Vec<[i32; 3]> = self.board.tiles.iter().enumerate()
+            .filter(|&(_, &tile)| tile == Charger )
+            .map(|(i, _)| self.board.position(i) )
+            .collect();
+        self.paths = DroneKind::ALL.iter()
+            .map(|kind| topology::dijkstra(&self.board, kind, &targets) )
+            .chain(DroneKind::ALL.iter().map(|kind| topology::dijkstra(&self.board, kind, &chargers) ))
+            .chain(self.destinations.iter().flat_map(|&destination| DroneKind::ALL.iter().map(move |kind| {
+                topology::dijkstra(board, kind, &[destination])
+            })))
+            .collect();
+        self.closest = match self.target {
+            Some(target) if self.settings.fallback == Fallback::Closest => DroneKind::ALL.iter()
+                .map(|&kind| fallback::closest(&self.board, kind, &self.paths[kind as usize], target) )
+                .collect(),
+            _ => Vec::new(),
+        };
+        self.visited.resize(self.board.tiles.len(), 0);
+        self.emit(Event::PathsRecomputed);
+        self.count_trapped();
+    }
+
+    /// Updates `stats.trapped`, `stats.stranded` and `Drone::trapped`
+    fn count_trapped(&mut self) {
+        self.stats.stranded = match self.settings.battery {
+            Some(capacity) => self.drones.energy.iter().filter(|&&energy| energy.min(capacity) == 0 ).count(),
+            None => 0,
+        };
+        self.stats.trapped = 0;
+        for i in 0..self.drones.len() {
+            let tile = self.drones.tile(i);
+            let field = &self.paths[self.drones.field(i)];
+            let trapped = self.board.contains(tile)  &&  field.get(&self.board, tile).is_none();
+            if trapped  &&  !self.drones.trapped[i] {
+                let id = self.drones.id[i];
+                self.emit(Event::Trapped{drone: id,  pos: tile});
+            }
+            self.drones.trapped[i] = trapped;
+            self.stats.trapped += trapped as usize;
+        }
+    }
+
+    /// Removes the current target if there is one, and places it at `to`
+    pub fn move_target(&mut self,  to: Option<[i32; 3]>) {
+        self.place_target(to);
+        self.update_paths();
+    }
+    /// `move_target()` without recomputing paths, for several changes in a row.
+    pub fn place_target(&mut self,  to: Option<[i32; 3]>) {
+        if let Some(target) = self.target.take() {
+            self.board[target] = Open;
+        }
+        if let Some(pos) = to {
+            self.board[pos] = Target;
+            self.target = Some(pos);
+        }
+        self.emit(Event::TargetMoved{to});
+    }
+
+    /// Changes a tile other than the target, without recomputing paths.
+    /// Use `build_walls()` to place walls.
+    pub fn set_tile(&mut self,  pos: [i32; 3],  tile: Tile) {
+        self.board[pos] = tile;
+        self.emit(Event::TileChanged{pos,  tile});
+    }
+
+    /// Places walls on the tiles, and removes, moves or avoids drones on them
+    /// depending on `settings.on_wall`.
+    pub fn build_walls(&mut self,  tiles: &[[i32; 3]]) {
+        self.place_walls(tiles);
+        self.update_paths();
+    }
+    /// `build_walls()` without recomputing paths, for several changes in a row.
+    pub fn place_walls(&mut self,  tiles: &[[i32; 3]]) {
+        for &p in tiles {
+            if self.settings.on_wall == WallPolicy::Refuse
+            && (0..self.drones.len()).any(|i| self.drones.tile(i) == p  &&  !self.drones.kind[i].can_enter(Wall) ) {
+                continue;
+            }
+            if self.board[p] == Target {
+                self.target = None;
+                self.emit(Event::TargetMoved{to: None});
+            }
+            self.board[p] = Wall;
+            self.emit(Event::TileChanged{pos: p,  tile: Wall});
+        }
+        if self.settings.on_wall == WallPolicy::Relocate {
+            for i in 0..self.drones.len() {
+                let tile = self.drones.tile(i);
+                let kind = self.drones.kind[i];
+                if self.board.get(tile) == Some(Wall)  &&  !kind.can_enter(Wall) {
+                    // if there is no open tile it's destroyed in the next tick
+                    if let Some(open) = self.nearest_open(tile, kind) {
+                        let pos = Simulation::spawn_position(open);
+                        self.drones.pos[i] = pos;
+                        self.drones.prev[i] = pos;
+                        self.drones.vel[i] = [0.0, 0.0];
+                        self.stats.relocated += 1;
+                        let id = self.drones.id[i];
+                        self.emit(Event::Moved{drone: id,  to: open});
+                    }
+                }
+            }
+        }
+    }
+
+    /// Breadth-first search through walls for a tile the kind can enter.
+    fn nearest_open(&self,  from: [i32; 3],  kind: DroneKind) -> Option<[i32; 3]> {
+        let mut visited = vec![false; self.board.node_count()];
+        let mut queue = VecDeque::new();
+        visited[self.board.index(from)] = true;
+        queue.push_back(from);
+        while let Some(p) = queue.pop_front() {
+            if kind.can_enter(self.board[p]) {
+                return Some(p);
+            }
+            self.board.neighbours(p, |n| {
+                if !visited[self.board.index(n)] {
+                    visited[self.board.index(n)] = true;
+                    queue.push_back(n);
+                }
+            });
+        }
+        None
+    }
+
+    pub fn add_floor(&mut self) {
+        self.board.add_floor();
+        self.update_paths();
+    }
+    /// Drones and the target on the top floor are removed with it.
+    pub fn remove_floor(&mut self) {
+        if self.board.floors() > 1 {
+            let removed = self.board.floors()-1;
+            if self.target.is_some_and(|target| target[2] == removed ) {
+                self.target = None;
+            }
+            let (kept, lost): (Vec<Drone>, Vec<Drone>) = self.drones.iter().partition(|d| d.tile()[2] < removed );
+            self.drones = kept.into_iter().collect();
+            for drone in lost {
+                self.stats.destroyed += 1;
+                self.emit(Event::Destroyed{drone: drone.id,  pos: drone.tile()});
+            }
+            self.board.remove_floor();
+            self.update_paths();
+        }
+    }
+}
+
+
+/// What moving drones reads, shared between threads.
+struct Mover<'a> {
+    board: &'a Board,
+    paths: &'a [FlowField<[i32; 3]>],
+    settings: &'a Settings,
+    /// Empty with `Movement::Classic`
+    accelerations: &'a [[f64; 2]],
+    fallbacks: Fallbacks<'a>,
+    seed: u64,
+    ticks: u64,
+} impl<'a> Mover<'a> {
+    fn move_drones(&self,  drones: &mut DronesMut) {
+        for j in 0..drones.len() {
+            self.move_drone(drones, j);
+        }
+    }
+
+    fn move_drone(&self,  d: &mut DronesMut,  j: usize) {
+        let pos = tile_of(d.pos[j]);
+        let kind = d.kind[j];
+        if self.board[pos] == Target  ||  !kind.can_enter(self.board[pos])  ||  !self.battery(d, j, pos) {
+            return;
+        }
+        let m = d.pos[j];
+        let steps = d.steps[j];
+        match self.paths[d.field(j)].get(self.board, pos) {
+            Some(path) if path.next[2] != pos[2] => {// change floor
+                d.pos[j][2] = path.next[2] as f64;
+                d.steps[j] += 1;
+            },
+            _ if self.settings.movement == Movement::Steering => {
+                steering::apply(self.board, kind, &mut d.pos[j], &mut d.vel[j],
+                                self.accelerations[d.start+j], &self.settings.steering);
+                if tile_of(d.pos[j]) != pos {
+                    d.steps[j] += 1;
+                }
+            },
+            Some(path) if path.next == pos => {},// waiting at a destination
+            Some(path) if !self.moves_this_tick(d.born[j], kind.speed_on(self.board[path.next])) => {},
+            Some(path) => {// move along
+                let dir = vec3_sub(path.next, pos);
+                let dir = [dir[0] as f64, dir[1] as f64, dir[2] as f64];
+                d.pos[j] = vec3_add(m, dir);
+                d.steps[j] += 1;
+            },
+            None if self.settings.fallback != Fallback::Jitter => {
+                let mut rng = drone_rng(self.seed, self.ticks, d.id[j]);
+                match self.fallbacks.next_tile(self.board, kind, pos, &mut rng) {
+                    Some(next) if next[2] != pos[2]  ||  self.moves_this_tick(d.born[j], kind.speed_on(self.board[next])) => {
+                        let dir = vec3_sub(next, pos);
+                        d.pos[j] = vec3_add(m, [dir[0] as f64, dir[1] as f64, dir[2] as f64]);
+                        d.steps[j] += 1;
+                    }
+                    _ => {}
+                }
+            },
+            None => {// jitter randomly
+                let mut rng = drone_rng(self.seed, self.ticks, d.id[j]);
+                let min = [(m[0] as i32)as f64, (m[1] as i32)as f64];
+                let max = vec2_add(min, [0.6,0.6]);
+                let x = m[0] + rng.sample::<f64,_>(Open01) - 0.5;
+                let y = m[1] + rng.sample::<f64,_>(Open01) - 0.5;
+                if x >= min[0]  &&  x <= max[0] {
+                    d.pos[j][0] = x;
+                }
+                if y >= min[1]  &&  y <= max[1] {
+                    d.pos[j][1] = y;
+                }
+            },
+        }
+        if let (Some(capacity), true) = (self.settings.battery, d.steps[j] != steps) {
+            d.energy[j] = d.energy[j].min(capacity).saturating_sub(1);
+        }
+    }
+
+    /// Whether a drone slower than one tile per tick moves this tick,
+    /// in classic movement. `speed` is from `DroneKind::speed_on()`.
+    fn moves_this_tick(&self,  born: u64,  speed: f64) -> bool {
+        let age = (self.ticks - born) as f64;
+        (age*speed).floor() > ((age-1.0)*speed).floor()
+    }
+
+    /// Charges, stops or sends drone `j` towards a charger,
+    /// and returns whether it can move this tick.
+    fn battery(&self,  d: &mut DronesMut,  j: usize,  tile: [i32; 3]) -> bool {
+        let capacity = match self.settings.battery {
+            Some(capacity) => capacity,
+            None => {
+                d.charging[j] = false;
+                return true;
+            }
+        };
+        let energy = d.energy[j].min(capacity);
+        if d.charging[j]  &&  self.board[tile] == Charger {
+            d.energy[j] = energy.saturating_add(self.settings.charge_rate).min(capacity);
+            d.charging[j] = d.energy[j] < capacity;
+            d.vel[j] = [0.0, 0.0];
+            return false;
+        } else if energy == 0 {
+            d.vel[j] = [0.0, 0.0];
+            return false;
+        }
+        if energy < self.settings.low_battery {
+            d.charging[j] = true;
+        }
+        if d.charging[j]  &&  self.paths[d.field(j)].get(self.board, tile).is_none() {
+            d.charging[j] = false; // no reachable charger, so keep going
+        }
+        true
+    }
+}
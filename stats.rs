@@ -0,0 +1,92 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Counting what happens to drones during a simulation.
+
+use std::collections::VecDeque;
+
+/// How many tiles each bar of `Stats::path_lengths` covers.
+pub const PATH_LENGTH_BUCKET: u32 = 5;
+/// Simulated seconds per tick, which is also how long a tick lasts at normal speed.
+pub const TICK_LENGTH: f64 = 0.2;
+/// How many of the most recent ticks `Stats::throughput()` counts arrivals in.
+pub const THROUGHPUT_WINDOW: u64 = 50;
+
+
+/// Totals since the simulation was created, except `trapped` and `stranded`.
+#[derive(Clone, Default, PartialEq,Eq, Debug)]
+pub struct Stats {
+    /// Drones created by spawners.
+    pub spawned: u64,
+    /// Drones that reached the target, each counted once.
+    pub arrived: u64,
+    /// Drones that were inside a wall and removed.
+    pub destroyed: u64,
+    /// Drones that were moved out of walls placed on them.
+    pub relocated: u64,
+    /// Drones created by the user or scripts.
+    pub placed: u64,
+    /// Drones removed by the user.
+    pub removed: u64,
+    /// Drones that currently cannot reach where they are going.
+    pub trapped: usize,
+    /// Drones that currently have no energy left.
+    pub stranded: usize,
+    /// The sum of ticks from creation to arrival, for `mean_travel_time()`.
+    pub total_travel_time: u64,
+    pub max_travel_time: u64,
+    /// The number of arrived drones that moved between
+    /// `i*PATH_LENGTH_BUCKET` and `(i+1)*PATH_LENGTH_BUCKET` tiles.
+    pub path_lengths: Vec<u64>,
+    /// The ticks of arrivals during the last `THROUGHPUT_WINDOW` ticks, oldest first.
+    pub recent_arrivals: VecDeque<u64>,
+} impl Stats {
+    /// Records a drone reaching the target at `tick`.
+    pub fn arrival(&mut self,  tick: u64,  travel_time: u64,  path_length: u32) {
+        self.arrived += 1;
+        while self.recent_arrivals.front().is_some_and(|&t| t + THROUGHPUT_WINDOW <= tick ) {
+            self.recent_arrivals.pop_front();
+        }
+        self.recent_arrivals.push_back(tick);
+        self.total_travel_time += travel_time;
+        self.max_travel_time = self.max_travel_time.max(travel_time);
+        let bucket = (path_length / PATH_LENGTH_BUCKET) as usize;
+        if bucket >= self.path_lengths.len() {
+            self.path_lengths.resize(bucket+1, 0);
+        }
+        self.path_lengths[bucket] += 1;
+    }
+
+    /// In ticks, or `None` if no drone has arrived yet.
+    pub fn mean_travel_time(&self) -> Option<f64> {
+        if self.arrived == 0 {
+            None
+        } else {
+            Some(self.total_travel_time as f64 / self.arrived as f64)
+        }
+    }
+
+    /// Arrivals per simulated second during the last `THROUGHPUT_WINDOW`
+    /// ticks before `now`, or fewer at the start.
+    pub fn throughput(&self,  now: u64) -> f64 {
+        let window = now.min(THROUGHPUT_WINDOW);
+        if window == 0 {
+            return 0.0;
+        }
+        let arrivals = self.recent_arrivals.iter().filter(|&&t| t + window > now ).count();
+        arrivals as f64 / (window as f64 * TICK_LENGTH)
+    }
+}
@@ -0,0 +1,259 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Moving drones with velocity and acceleration instead of one tile at a time.
+//!
+//! Each drone accelerates towards the next tile of the flow field, and is also
+//! pushed away from drones and walls that are too close and towards the
+//! velocity of drones around it, as described by Craig Reynolds in
+//! "Steering Behaviors For Autonomous Characters".
+//! All distances are in tiles and all times in ticks.
+
+use rand::Rng;
+use rand::distributions::Open01;
+use vecmath::{vec2_add,vec2_sub,vec2_scale,vec2_len}; // Vector2 is [T; 2]
+use board::Board;
+use topology::{Topology,FlowField};
+use drones::{Drones,DRONE_SIZE,drone_rng,map_indices,tile_of};
+use kind::DroneKind;
+use fallback::{Fallback,Fallbacks};
+
+
+/// Only this many drones in each nearby tile affect a drone, so that crowded
+/// tiles don't make ticks quadratically slower.
+const MAX_PER_TILE: usize = 16;
+
+
+#[derive(Clone,Copy, PartialEq, Debug)]
+pub struct SteeringSettings {
+    /// In tiles per tick
+    pub max_speed: f64,
+    /// How much the velocity can change per tick
+    pub max_acceleration: f64,
+    /// Drones closer than this push each other away.
+    pub separation_radius: f64,
+    pub separation_weight: f64,
+    /// Drones closer than this try to move in the same direction.
+    pub alignment_radius: f64,
+    pub alignment_weight: f64,
+    /// Walls closer than this push drones away.
+    pub wall_radius: f64,
+    pub wall_weight: f64,
+    /// Random acceleration for drones that cannot reach the target,
+    /// with `Fallback::Jitter`.
+    pub wander: f64,
+}
+impl Default for SteeringSettings {
+    fn default() -> SteeringSettings {
+        SteeringSettings {
+            max_speed: 0.5,
+            max_acceleration: 0.2,
+            separation_radius: 0.5,
+            separation_weight: 1.0,
+            alignment_radius: 1.0,
+            alignment_weight: 0.3,
+            wall_radius: 0.3,
+            wall_weight: 1.0,
+            wander: 0.1,
+        }
+    }
+}
+
+
+fn center(pos: [f64; 3]) -> [f64; 2] {
+    [pos[0] + DRONE_SIZE/2.0,  pos[1] + DRONE_SIZE/2.0]
+}
+
+/// Shortens `v` if it's longer than `max`
+fn limit(v: [f64; 2],  max: f64) -> [f64; 2] {
+    let len = vec2_len(v);
+    if len > max {vec2_scale(v, max/len)} else {v}
+}
+
+/// Lengthens or shortens `v` to `len`, unless it's zero.
+fn with_length(v: [f64; 2],  len: f64) -> [f64; 2] {
+    let current = vec2_len(v);
+    if current > 0.0 {vec2_scale(v, len/current)} else {v}
+}
+
+/// Tiles the kind cannot enter and the outside of the board are solid.
+fn is_solid(board: &Board,  kind: DroneKind,  tile: [i32; 3]) -> bool {
+    board.get(tile).is_none_or(|tile| !kind.can_enter(tile) )
+}
+
+/// Relative to `SteeringSettings::max_speed`, slower in mud for tracked drones.
+fn speed(board: &Board,  kind: DroneKind,  tile: [i32; 3]) -> f64 {
+    board.get(tile).map_or(kind.speed(), |tile| kind.speed_on(tile) )
+}
+
+/// Which drones are in each tile, to only look at those nearby.
+struct Buckets {
+    /// The drones in tile `t` are `drones[starts[t]..starts[t+1]]`
+    starts: Vec<usize>,
+    drones: Vec<usize>,
+} impl Buckets {
+    fn new(board: &Board,  drones: &Drones) -> Buckets {
+        let tile_index = |i: usize| {
+            let tile = drones.tile(i);
+            if board.contains(tile) {Some(Topology::index(board, tile))} else {None}
+        };
+        let mut starts = vec![0; board.tiles.len()+1];
+        for i in 0..drones.len() {
+            if let Some(t) = tile_index(i) {
+                starts[t+1] += 1;
+            }
+        }
+        for t in 1..starts.len() {
+            starts[t] += starts[t-1];
+        }
+        let mut next = starts.clone();
+        let mut sorted = vec![0; starts[board.tiles.len()]];
+        for i in 0..drones.len() {
+            if let Some(t) = tile_index(i) {
+                sorted[next[t]] = i;
+                next[t] += 1;
+            }
+        }
+        Buckets{starts,  drones: sorted}
+    }
+    fn get(&self,  board: &Board,  tile: [i32; 3]) -> &[usize] {
+        let t = Topology::index(board, tile);
+        &self.drones[self.starts[t]..self.starts[t+1]]
+    }
+}
+
+/// Computes the acceleration of every drone, from where they all are now.
+/// `paths` is `Simulation::paths`, indexed by `Drone::field()`.
+pub fn accelerations(board: &Board,  paths: &[FlowField<[i32; 3]>],  fallbacks: &Fallbacks,
+                     drones: &Drones,  settings: &SteeringSettings,  seed: u64,  tick: u64)
+-> Vec<[f64; 2]> {
+    let buckets = Buckets::new(board, drones);
+    let reach = settings.separation_radius.max(settings.alignment_radius).ceil() as i32;
+
+    map_indices(drones.len(), |i| {
+        let here = center(drones.pos[i]);
+        let tile = drones.tile(i);
+        let kind = drones.kind[i];
+        let vel = drones.vel[i];
+        let max_speed = settings.max_speed * speed(board, kind, tile);
+        let desired = match paths[drones.field(i)].get(board, tile) {
+            Some(step) if step.next != tile => {
+                let next = [step.next[0] as f64 + 0.5,  step.next[1] as f64 + 0.5];
+                with_length(vec2_sub(next, here), max_speed)
+            }
+            Some(_) => [0.0, 0.0], // at the target or a charger
+            None if fallbacks.fallback == Fallback::Jitter => {
+                let mut rng = drone_rng(seed, tick, drones.id[i]);
+                let x = rng.sample::<f64,_>(Open01) - 0.5;
+                let y = rng.sample::<f64,_>(Open01) - 0.5;
+                vec2_add(vel, [x*settings.wander, y*settings.wander])
+            }
+            None => {
+                let mut rng = drone_rng(seed, tick, drones.id[i]);
+                match fallbacks.next_tile(board, kind, tile, &mut rng) {
+                    Some(next) if next[2] == tile[2] => {
+                        let next = [next[0] as f64 + 0.5,  next[1] as f64 + 0.5];
+                        with_length(vec2_sub(next, here), max_speed)
+                    }
+                    _ => [0.0, 0.0],
+                }
+            }
+        };
+        let mut steering = vec2_sub(desired, vel);
+
+        let mut separation = [0.0, 0.0];
+        let mut velocities = [0.0, 0.0];
+        let mut aligning = 0;
+        for y in tile[1]-reach..=tile[1]+reach {
+            for x in tile[0]-reach..=tile[0]+reach {
+                if !board.contains([x,y,tile[2]]) {
+                    continue;
+                }
+                for &other in buckets.get(board, [x,y,tile[2]]).iter().take(MAX_PER_TILE) {
+                    if other == i {
+                        continue;
+                    }
+                    let away = vec2_sub(here, center(drones.pos[other]));
+                    let distance = vec2_len(away);
+                    if distance < settings.separation_radius {
+                        let strength = 1.0 - distance/settings.separation_radius;
+                        // drones on top of each other are pushed apart in a fixed direction
+                        let away = if distance > 0.0 {away} else {[(i as f64).cos(), (i as f64).sin()]};
+                        separation = vec2_add(separation, with_length(away, strength));
+                    }
+                    if distance < settings.alignment_radius {
+                        velocities = vec2_add(velocities, drones.vel[other]);
+                        aligning += 1;
+                    }
+                }
+            }
+        }
+        steering = vec2_add(steering, vec2_scale(separation, settings.separation_weight*settings.max_speed));
+        if aligning > 0 {
+            let average = vec2_scale(velocities, 1.0/aligning as f64);
+            let alignment = vec2_sub(average, vel);
+            steering = vec2_add(steering, vec2_scale(alignment, settings.alignment_weight));
+        }
+
+        let mut avoidance = [0.0, 0.0];
+        for y in tile[1]-1..=tile[1]+1 {
+            for x in tile[0]-1..=tile[0]+1 {
+                if !is_solid(board, kind, [x,y,tile[2]]) {
+                    continue;
+                }
+                let closest = [here[0].max(x as f64).min(x as f64 + 1.0),
+                               here[1].max(y as f64).min(y as f64 + 1.0)];
+                let away = vec2_sub(here, closest);
+                let distance = vec2_len(away) - DRONE_SIZE/2.0;
+                if distance < settings.wall_radius {
+                    let strength = 1.0 - distance.max(0.0)/settings.wall_radius;
+                    avoidance = vec2_add(avoidance, with_length(away, strength));
+                }
+            }
+        }
+        steering = vec2_add(steering, vec2_scale(avoidance, settings.wall_weight*settings.max_speed));
+
+        limit(steering, settings.max_acceleration)
+    })
+}
+
+/// Accelerates and moves a drone, stopping at walls.
+pub fn apply(board: &Board,  kind: DroneKind,  pos: &mut [f64; 3],  vel: &mut [f64; 2],
+             acceleration: [f64; 2],  settings: &SteeringSettings) {
+    *vel = limit(vec2_add(*vel, acceleration), settings.max_speed * speed(board, kind, tile_of(*pos)));
+    let floor = pos[2] as i32;
+    // Move along one axis at a time, so that drones slide along walls.
+    for axis in 0..2 {
+        let mut moved = *pos;
+        moved[axis] += vel[axis];
+        // the tiles the drone overlaps after moving
+        let first = [moved[0].floor() as i32,  moved[1].floor() as i32];
+        let last = [(moved[0]+DRONE_SIZE).floor() as i32,  (moved[1]+DRONE_SIZE).floor() as i32];
+        let blocked = (first[1]..=last[1]).any(|y| (first[0]..=last[0]).any(|x| {
+            is_solid(board, kind, [x,y,floor])
+        }));
+        if !blocked {
+            *pos = moved;
+        } else if vel[axis] > 0.0 {
+            // stop at the edge of the tile the drone is in
+            pos[axis] = (pos[axis]+DRONE_SIZE).ceil() - DRONE_SIZE - 1e-9;
+            vel[axis] = 0.0;
+        } else {
+            pos[axis] = pos[axis].floor();
+            vel[axis] = 0.0;
+        }
+    }
+}
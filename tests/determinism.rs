@@ -0,0 +1,71 @@
+extern crate pistonpath;
+
+use pistonpath::board::Tile;
+use pistonpath::scenario::Scenario;
+use pistonpath::sim::Simulation;
+
+// The right half is walled off, so the drones there jitter randomly.
+const SCENARIO: &str = "
+floor
+.........#..........
+.........#..........
+.........#..........
+....T....#..........
+.........#..........
+.........#..........
+floor
+..S......#.........S
+.........#..........
+.........#..........
+.........#..........
+.........#..........
+.........#..........
+drone 0.3 0.3 0
+drone 15.3 2.3 0
+drone 12.6 5.1 0
+drone 18.3 4.3 1
+drone 11.3 0.3 1
+";
+
+/// Runs for some ticks and changes tiles along the way, like a user would.
+fn run(seed: u64,  ticks: u64) -> Simulation {
+    let mut sim = Simulation::new(Scenario::parse(SCENARIO).unwrap(), seed);
+    for _ in 0..ticks {
+        match sim.ticks {
+            50 => {
+                sim.board[[2,0,0]] = Tile::Stairs;
+                sim.update_paths();
+            }
+            100 => sim.move_target(Some([15,3,0])),
+            150 => {
+                sim.board[[9,5,0]] = Tile::Open;
+                sim.update_paths();
+            }
+            _ => {}
+        }
+        sim.tick();
+    }
+    sim
+}
+
+fn drone_bits(sim: &Simulation) -> Vec<[u64; 3]> {
+    sim.drones.iter()
+        .map(|d| [d.pos[0].to_bits(), d.pos[1].to_bits(), d.pos[2].to_bits()] )
+        .collect()
+}
+
+#[test]
+fn same_seed_gives_identical_drones() {
+    for &seed in &[0, 1, 0xdead_beef, u64::MAX] {
+        let a = run(seed, 300);
+        let b = run(seed, 300);
+        assert_eq!(a.seed(), seed);
+        assert_eq!(drone_bits(&a), drone_bits(&b), "seed {}", seed);
+    }
+}
+
+#[test]
+fn jitter_depends_on_seed() {
+    // before the wall is opened, so the drones are still trapped
+    assert_ne!(drone_bits(&run(1, 100)), drone_bits(&run(2, 100)));
+}
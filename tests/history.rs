@@ -0,0 +1,130 @@
+extern crate pistonpath;
+
+use std::collections::HashMap;
+use pistonpath::board::Tile;
+use pistonpath::history::History;
+use pistonpath::kind::DroneKind;
+use pistonpath::scenario::Scenario;
+use pistonpath::sim::Simulation;
+
+const SCENARIO: &str = "\
+floor
+S.......
+........
+...T....
+........
+floor
+S.......
+........
+........
+........
+drone 0.3 0.3 0
+";
+
+/// The tiles and spawner kinds, which is what edits change.
+type State = (Vec<Tile>, HashMap<[i32; 3], DroneKind>);
+
+fn state(sim: &Simulation) -> State {
+    (sim.board.tiles.clone(), sim.spawner_kinds.clone())
+}
+
+/// Makes some edits like a user would, and returns the state before the
+/// first one and after each of them.
+fn edit(sim: &mut Simulation,  history: &mut History) -> Vec<State> {
+    let mut states = vec![state(sim)];
+    // a wall drawn in one drag
+    history.begin(sim);
+    sim.build_walls(&[[5,0,0], [5,1,0], [5,2,0]]);
+    history.end(sim);
+    states.push(state(sim));
+    // a spawner of tracked drones on the upper floor
+    history.begin(sim);
+    sim.set_tile([6,3,1], Tile::Spawner);
+    sim.spawner_kinds.insert([6,3,1], DroneKind::Tracked);
+    history.end(sim);
+    states.push(state(sim));
+    // a new floor with mud on it
+    history.begin(sim);
+    sim.add_floor();
+    sim.set_tile([1,1,2], Tile::Mud);
+    history.end(sim);
+    states.push(state(sim));
+    // the target moved and part of the wall removed
+    history.begin(sim);
+    sim.move_target(Some([7,3,0]));
+    sim.set_tile([5,1,0], Tile::Open);
+    history.end(sim);
+    states.push(state(sim));
+    states
+}
+
+fn scenario(sim: &Simulation,  history: &History) -> Scenario {
+    Scenario {
+        seed: Some(sim.seed()),
+        board: sim.board.clone(),
+        drones: sim.drones.iter().collect(),
+        spawner_kinds: sim.spawner_kinds.clone(),
+        settings: sim.settings.clone(),
+        history: history.clone(),
+    }
+}
+
+#[test]
+fn saved_history_can_be_undone_and_redone() {
+    let mut sim = Simulation::new(Scenario::parse(SCENARIO).unwrap(), 1);
+    let mut history = History::default();
+    let states = edit(&mut sim, &mut history);
+    assert_eq!(history.undo.len(), 4);
+    // leave something to redo
+    assert!(history.undo(&mut sim));
+    assert_eq!(state(&sim), states[3]);
+    assert_eq!((history.undo.len(), history.redo.len()), (3, 1));
+
+    let text = scenario(&sim, &history).to_string();
+    let mut loaded = Scenario::parse(&text).unwrap();
+    assert_eq!(loaded.history.undo, history.undo);
+    assert_eq!(loaded.history.redo, history.redo);
+    assert_eq!(loaded.spawner_kinds, sim.spawner_kinds);
+    assert_eq!(loaded.to_string(), text);
+
+    let mut history = std::mem::take(&mut loaded.history);
+    let mut sim = Simulation::new(loaded, 1);
+    assert_eq!(state(&sim), states[3]);
+    assert!(history.redo(&mut sim));
+    assert_eq!(state(&sim), states[4]);
+    assert_eq!(sim.target, Some([7,3,0]));
+    assert!(!history.redo(&mut sim));
+    for i in (0..4).rev() {
+        assert!(history.undo(&mut sim));
+        assert_eq!(state(&sim), states[i], "after undoing edit {}", i);
+    }
+    assert!(!history.undo(&mut sim));
+    assert_eq!(sim.target, Some([3,2,0]));
+    assert_eq!(sim.board.floors(), 2);
+    for (i, after) in states.iter().enumerate().skip(1) {
+        assert!(history.redo(&mut sim));
+        assert_eq!(state(&sim), *after, "after redoing edit {}", i-1);
+    }
+}
+
+#[test]
+fn editing_after_undo_forgets_what_was_undone() {
+    let mut sim = Simulation::new(Scenario::parse(SCENARIO).unwrap(), 1);
+    let mut history = History::default();
+    let states = edit(&mut sim, &mut history);
+    assert!(history.undo(&mut sim));
+    assert!(history.undo(&mut sim));
+    assert_eq!(state(&sim), states[2]);
+    history.begin(&sim);
+    sim.set_tile([0,3,0], Tile::Charger);
+    history.end(&sim);
+    assert!(history.redo.is_empty());
+    assert!(!history.redo(&mut sim));
+    assert!(history.undo(&mut sim));
+    assert_eq!(state(&sim), states[2]);
+
+    let text = scenario(&sim, &history).to_string();
+    let loaded = Scenario::parse(&text).unwrap();
+    assert_eq!(loaded.history.undo, history.undo);
+    assert_eq!(loaded.history.redo, history.redo);
+}
@@ -0,0 +1,286 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Going back and forward in time.
+
+use std::collections::VecDeque;
+use sim::{Simulation,Snapshot};
+
+
+/// How often to store all drones instead of only those that changed.
+const KEYFRAME_INTERVAL: usize = 16;
+
+
+/// The most recent snapshots of a simulation, oldest first, and which of
+/// them the simulation is at.
+///
+/// Recording or editing after going back throws away the snapshots after the
+/// current one, so that the simulation branches from there.
+///
+/// Every `KEYFRAME_INTERVAL`th snapshot and the oldest one are complete,
+/// the others only store what changed since the snapshot before them.
+pub struct Timeline {
+    snapshots: VecDeque<Snapshot>,
+    /// The complete version of the newest snapshot
+    newest: Option<Snapshot>,
+    current: usize,
+    /// The most snapshots to keep.
+    capacity: usize,
+    /// The most drones to keep in all snapshots together, as there can be very many.
+    max_drones: usize,
+    stored_drones: usize,
+} impl Timeline {
+    pub fn new(capacity: usize,  max_drones: usize) -> Timeline {
+        Timeline {
+            snapshots: VecDeque::new(),
+            newest: None,
+            current: 0,
+            capacity: capacity.max(1),
+            max_drones,
+            stored_drones: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+    /// The index of the snapshot the simulation is at.
+    pub fn position(&self) -> usize {
+        self.current
+    }
+    /// Whether there are snapshots after the current one.
+    pub fn is_rewound(&self) -> bool {
+        self.current+1 < self.snapshots.len()
+    }
+    /// The ticks of the oldest and newest snapshots
+    pub fn range(&self) -> Option<(u64, u64)> {
+        match (self.snapshots.front(), self.snapshots.back()) {
+            (Some(first), Some(last)) => Some((first.ticks(), last.ticks())),
+            _ => None,
+        }
+    }
+
+    /// Adds a snapshot of the simulation after the current one.
+    pub fn record(&mut self,  sim: &Simulation) {
+        self.branch();
+        let complete = sim.snapshot_sharing(self.newest.as_ref());
+        let since_keyframe = self.snapshots.iter().rev().take_while(|s| !s.is_complete() ).count();
+        let snapshot = match self.newest {
+            Some(ref newest) if since_keyframe+1 < KEYFRAME_INTERVAL => complete.changes_since(newest),
+            _ => complete.clone(),
+        };
+        self.stored_drones += snapshot.stored_drones();
+        self.snapshots.push_back(snapshot);
+        self.newest = Some(complete);
+        while self.snapshots.len() > self.capacity
+        || (self.stored_drones > self.max_drones  &&  self.snapshots.len() > 1) {
+            let mut oldest = self.snapshots.pop_front().unwrap();
+            self.stored_drones -= oldest.stored_drones();
+            let next = self.snapshots.front_mut().unwrap();
+            if !next.is_complete() {
+                self.stored_drones -= next.stored_drones();
+                oldest.apply(next);
+                *next = oldest;
+                self.stored_drones += next.stored_drones();
+            }
+        }
+        self.current = self.snapshots.len()-1;
+    }
+
+    /// Throws away the snapshots after the current one.
+    pub fn branch(&mut self) {
+        if !self.is_rewound() {
+            return;
+        }
+        while self.is_rewound() {
+            let newest = self.snapshots.pop_back().unwrap();
+            self.stored_drones -= newest.stored_drones();
+        }
+        self.newest = self.complete(self.current);
+    }
+
+    /// Rebuilds snapshot `index` from the complete one before it.
+    fn complete(&self,  index: usize) -> Option<Snapshot> {
+        if index >= self.snapshots.len() {
+            return None;
+        }
+        let keyframe = (0..=index).rev().find(|&i| self.snapshots[i].is_complete() ).unwrap();
+        let mut snapshot = self.snapshots[keyframe].clone();
+        for next in self.snapshots.range(keyframe+1..=index) {
+            snapshot.apply(next);
+        }
+        Some(snapshot)
+    }
+
+    /// Restores snapshot `index`, and returns false if there is no such snapshot.
+    pub fn seek(&mut self,  sim: &mut Simulation,  index: usize) -> bool {
+        match self.complete(index) {
+            Some(snapshot) => {
+                sim.restore(&snapshot);
+                self.current = index;
+                true
+            }
+            None => false,
+        }
+    }
+    /// Restores the previous snapshot if there is one.
+    pub fn back(&mut self,  sim: &mut Simulation) -> bool {
+        self.current > 0  &&  self.seek(sim, self.current-1)
+    }
+    /// Restores the next snapshot if there is one.
+    pub fn forward(&mut self,  sim: &mut Simulation) -> bool {
+        self.seek(sim, self.current+1)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scenario::Scenario;
+    use board::Tile;
+
+    /// Drones keep coming from the spawner, and some are walled off and
+    /// jitter, so that the random number generator is used.
+    const SCENARIO: &str = "\
+floor
++.....#...
+......#...
+..~~..#...
+......#.T.
+......#...
+drone 8.3 0.3 0
+drone 8.3 4.3 0
+spawn_interval 3
+on_arrival despawn
+";
+
+    /// Records `ticks` ticks with some edits along the way, and returns
+    /// complete snapshots taken after each of them.
+    fn record(timeline: &mut Timeline,  sim: &mut Simulation,  ticks: u64) -> Vec<Snapshot> {
+        let mut full = Vec::new();
+        for _ in 0..ticks {
+            match sim.ticks {
+                20 => sim.build_walls(&[[3,2,0], [3,3,0]]),
+                45 => {
+                    sim.set_tile([6,4,0], Tile::Open);
+                    sim.update_paths();
+                }
+                70 => sim.move_target(Some([9,0,0])),
+                _ => {}
+            }
+            sim.tick();
+            timeline.record(sim);
+            full.push(sim.snapshot());
+        }
+        full
+    }
+
+    /// The complete snapshot taken at the same tick as the simulation is at.
+    fn taken_at(full: &[Snapshot],  sim: &Simulation) -> Snapshot {
+        full.iter().find(|s| s.ticks() == sim.ticks ).unwrap().clone()
+    }
+
+    #[test]
+    fn seeking_restores_the_same_state() {
+        let mut sim = Simulation::new(Scenario::parse(SCENARIO).unwrap(), 3);
+        let mut timeline = Timeline::new(40, usize::MAX);
+        let full = record(&mut timeline, &mut sim, 100);
+        assert_eq!(timeline.len(), 40);
+        assert_eq!(timeline.range(), Some((61, 100)));
+        assert_eq!(timeline.position(), 39);
+
+        // the oldest is complete, and the others at most KEYFRAME_INTERVAL apart
+        assert!(timeline.snapshots[0].is_complete());
+        let keyframes: Vec<usize> = (0..timeline.len()).filter(|&i| timeline.snapshots[i].is_complete() ).collect();
+        assert!(keyframes.len() < timeline.len());
+        assert!(keyframes.windows(2).all(|w| w[1]-w[0] <= KEYFRAME_INTERVAL ));
+        assert!(timeline.len() - keyframes.last().unwrap() <= KEYFRAME_INTERVAL);
+
+        for i in (0..timeline.len()).rev() {
+            assert!(timeline.seek(&mut sim, i));
+            assert_eq!(sim.ticks, 61 + i as u64);
+            assert!(sim.snapshot() == taken_at(&full, &sim), "snapshot {}", i);
+        }
+        assert!(!timeline.seek(&mut sim, timeline.len()));
+        assert_eq!(timeline.position(), 0);
+
+        // to a snapshot with only changes
+        let delta = (1..timeline.len()).find(|&i| !timeline.snapshots[i].is_complete() ).unwrap();
+        assert!(timeline.seek(&mut sim, delta));
+        assert!(timeline.forward(&mut sim));
+        assert!(sim.snapshot() == taken_at(&full, &sim));
+        assert!(timeline.back(&mut sim));
+        assert!(timeline.back(&mut sim));
+        assert_eq!(timeline.position(), delta-1);
+        assert!(sim.snapshot() == taken_at(&full, &sim));
+
+        // ticking from a restored snapshot continues the same way
+        timeline.seek(&mut sim, 10);
+        while sim.ticks < 100 {
+            sim.tick();
+        }
+        assert!(sim.snapshot() == *full.last().unwrap());
+    }
+
+    #[test]
+    fn branching_forgets_the_future() {
+        let mut sim = Simulation::new(Scenario::parse(SCENARIO).unwrap(), 5);
+        let mut timeline = Timeline::new(100, usize::MAX);
+        let full = record(&mut timeline, &mut sim, 50);
+        assert!(timeline.seek(&mut sim, 20));
+        assert!(timeline.is_rewound());
+        timeline.branch();
+        assert!(!timeline.is_rewound());
+        assert_eq!(timeline.len(), 21);
+        assert_eq!(timeline.range(), Some((1, 21)));
+        assert!(!timeline.forward(&mut sim));
+        assert!(timeline.newest.as_ref() == Some(&full[20]));
+
+        // recording continues from the branch, and differently
+        sim.build_walls(&[[1,0,0]]);
+        let branched = record(&mut timeline, &mut sim, 10);
+        assert_eq!(timeline.len(), 31);
+        assert_eq!(timeline.range(), Some((1, 31)));
+        assert!(sim.snapshot() != full[30]);
+        for i in (0..31).rev() {
+            timeline.seek(&mut sim, i);
+            let expected = if i > 20 {&branched[i-21]} else {&full[i]};
+            assert!(sim.snapshot() == *expected, "snapshot {}", i);
+        }
+        // recording while rewound also branches
+        timeline.seek(&mut sim, 5);
+        timeline.record(&sim);
+        assert_eq!(timeline.len(), 7);
+    }
+
+    #[test]
+    fn old_snapshots_are_evicted_when_there_are_too_many_drones() {
+        let mut sim = Simulation::new(Scenario::parse(SCENARIO).unwrap(), 7);
+        let mut timeline = Timeline::new(1000, 200);
+        let full = record(&mut timeline, &mut sim, 100);
+        assert!(timeline.len() < 100);
+        assert!(timeline.stored_drones <= 200);
+        let stored: usize = timeline.snapshots.iter().map(|s| s.stored_drones() ).sum();
+        assert_eq!(stored, timeline.stored_drones);
+        assert!(timeline.snapshots[0].is_complete());
+        assert!(timeline.seek(&mut sim, 0));
+        assert!(sim.snapshot() == taken_at(&full, &sim));
+    }
+}
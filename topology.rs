@@ -0,0 +1,387 @@
+/* Copyright (C) 2018 Torbjørn Birch Moltu
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Searches that work on any kind of map.
+//!
+//! Implement `Topology` to define which positions are next to each other,
+//! and `CostModel` to define how expensive it is to move between them.
+//! `Board` and `UniformCost` in the `board` module are the implementations
+//! used by the game.
+
+use std::collections::vec_deque::VecDeque;
+use std::collections::BinaryHeap;
+use std::cmp::Ordering;
+
+
+/// Which positions exist and which are next to each other.
+pub trait Topology {
+    type Node: Copy + Eq;
+    /// An upper bound for `index()`, used to size the results of searches.
+    fn node_count(&self) -> usize;
+    /// Must be unique for each node and less than `node_count()`.
+    fn index(&self,  node: Self::Node) -> usize;
+    /// Calls `f` with every node that is next to `node`.
+    ///
+    /// The searches go backwards from the destination, so if `a` is a
+    /// neighbour of `b` then `b` must also be a neighbour of `a`.
+    /// Make the `CostModel` return `None` for one of the directions to get
+    /// one-way connections.
+    fn neighbours<F: FnMut(Self::Node)>(&self,  node: Self::Node,  f: F);
+}
+
+/// How expensive it is to move between neighbours.
+pub trait CostModel<T: Topology> {
+    /// The cost of moving from `from` to its neighbour `to`,
+    /// or `None` if that isn't possible.
+    fn cost(&self,  topology: &T,  from: T::Node,  to: T::Node) -> Option<u32>;
+    /// A guess of the total cost of moving from `from` to `to`, used by A*.
+    /// It must never be higher than the real cost, and the default of zero
+    /// makes A* behave like Dijkstra.
+    fn estimate(&self,  _topology: &T,  _from: T::Node,  _to: T::Node) -> u32 {
+        0
+    }
+}
+
+
+/// Where to go from a node to get closer to the nearest source.
+#[derive(Clone,Copy, PartialEq,Eq, Debug)]
+pub struct Step<N> {
+    /// The total cost of getting to the nearest source.
+    pub distance: u32,
+    /// The neighbour to move to, or the node itself if it is a source.
+    pub next: N,
+}
+
+/// The result of searching from one or more sources to every reachable node.
+#[derive(Clone, Debug)]
+pub struct FlowField<N> {
+    steps: Vec<Option<Step<N>>>,
+} impl<N: Copy> FlowField<N> {
+    /// A field where no nodes can reach any source.
+    pub fn empty<T: Topology<Node=N>>(topology: &T) -> Self {
+        FlowField{ steps: vec![None; topology.node_count()] }
+    }
+    /// Is `None` if the node cannot reach any source.
+    pub fn get<T: Topology<Node=N>>(&self,  topology: &T,  node: N) -> Option<Step<N>> {
+        self.steps.get(topology.index(node)).cloned().unwrap_or(None)
+    }
+}
+
+
+/// Finds the shortest path from every node to the nearest source,
+/// where every possible move counts as one.
+pub fn breadth_first<T,C>(topology: &T,  cost: &C,  sources: &[T::Node]) -> FlowField<T::Node>
+where T: Topology, C: CostModel<T> {
+    let mut field = FlowField::empty(topology);
+    let mut to_check = VecDeque::new();
+    for &source in sources {
+        field.steps[topology.index(source)] = Some(Step{distance: 0,  next: source});
+        to_check.push_back(source);
+    }
+    while let Some(to) = to_check.pop_front() {
+        let distance = field.steps[topology.index(to)].unwrap().distance + 1;
+        topology.neighbours(to, |from| {
+            let step = &mut field.steps[topology.index(from)];
+            if step.is_none()  &&  cost.cost(topology, from, to).is_some() {
+                *step = Some(Step{distance,  next: to});
+                to_check.push_back(from);
+            }
+        });
+    }
+    field
+}
+
+
+/// A node waiting in a priority queue, where the lowest cost comes first,
+/// and ties are broken by insertion order to keep the results predictable.
+struct Queued<N> {
+    cost: u32,
+    order: u64,
+    node: N,
+} impl<N> PartialEq for Queued<N> {
+    fn eq(&self,  other: &Self) -> bool {
+        (self.cost, self.order) == (other.cost, other.order)
+    }
+} impl<N> Eq for Queued<N> {}
+impl<N> PartialOrd for Queued<N> {
+    fn partial_cmp(&self,  other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+} impl<N> Ord for Queued<N> {
+    fn cmp(&self,  other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap
+        (other.cost, other.order).cmp(&(self.cost, self.order))
+    }
+}
+
+/// Finds the cheapest path from every node to the nearest source.
+pub fn dijkstra<T,C>(topology: &T,  cost: &C,  sources: &[T::Node]) -> FlowField<T::Node>
+where T: Topology, C: CostModel<T> {
+    let mut field = FlowField::empty(topology);
+    let mut queue = BinaryHeap::new();
+    let mut order = 0;
+    for &source in sources {
+        field.steps[topology.index(source)] = Some(Step{distance: 0,  next: source});
+        queue.push(Queued{cost: 0,  order,  node: source});
+        order += 1;
+    }
+    while let Some(Queued{cost: distance, node: to, ..}) = queue.pop() {
+        if field.steps[topology.index(to)].unwrap().distance < distance {
+            continue; // was queued again with a lower cost
+        }
+        topology.neighbours(to, |from| {
+            if let Some(c) = cost.cost(topology, from, to) {
+                let distance = distance.saturating_add(c);
+                let step = &mut field.steps[topology.index(from)];
+                if step.is_none_or(|step| distance < step.distance ) {
+                    *step = Some(Step{distance,  next: to});
+                    queue.push(Queued{cost: distance,  order,  node: from});
+                    order += 1;
+                }
+            }
+        });
+    }
+    field
+}
+
+
+/// Finds the cheapest path from `from` to `to`.
+///
+/// Returns the total cost and the nodes on the way, including both ends.
+pub fn a_star<T,C>(topology: &T,  cost: &C,  from: T::Node,  to: T::Node) -> Option<(u32, Vec<T::Node>)>
+where T: Topology, C: CostModel<T> {
+    // came_from[i] is the previous node and the cost to get to node i
+    let mut came_from: Vec<Option<(T::Node, u32)>> = vec![None; topology.node_count()];
+    let mut queue = BinaryHeap::new();
+    let mut order = 0;
+    came_from[topology.index(from)] = Some((from, 0));
+    queue.push(Queued{cost: cost.estimate(topology, from, to),  order,  node: from});
+    while let Some(Queued{node: current, ..}) = queue.pop() {
+        if current == to {
+            let distance = came_from[topology.index(to)].unwrap().1;
+            let mut path = vec![to];
+            let mut at = to;
+            while at != from {
+                at = came_from[topology.index(at)].unwrap().0;
+                path.push(at);
+            }
+            path.reverse();
+            return Some((distance, path));
+        }
+        let distance = came_from[topology.index(current)].unwrap().1;
+        topology.neighbours(current, |next| {
+            if let Some(c) = cost.cost(topology, current, next) {
+                let distance = distance.saturating_add(c);
+                let entry = &mut came_from[topology.index(next)];
+                if entry.is_none_or(|(_, old)| distance < old ) {
+                    *entry = Some((current, distance));
+                    order += 1;
+                    let guess = distance.saturating_add(cost.estimate(topology, next, to));
+                    queue.push(Queued{cost: guess,  order,  node: next});
+                }
+            }
+        });
+    }
+    None
+}
+
+
+/// Numbers the parts of `nodes` that can reach each other, in the order the
+/// parts are first seen. Is indexed by `Topology::index()`, and `None` for
+/// nodes not in `nodes`.
+pub fn regions<T,C,I>(topology: &T,  cost: &C,  nodes: I) -> Vec<Option<u32>>
+where T: Topology, C: CostModel<T>, I: IntoIterator<Item=T::Node> {
+    let mut region = vec![None; topology.node_count()];
+    let mut regions = 0;
+    let mut to_check = Vec::new();
+    for node in nodes {
+        if region[topology.index(node)].is_some() {
+            continue;
+        }
+        region[topology.index(node)] = Some(regions);
+        to_check.push(node);
+        while let Some(from) = to_check.pop() {
+            topology.neighbours(from, |to| {
+                if region[topology.index(to)].is_none()  &&  cost.cost(topology, from, to).is_some() {
+                    region[topology.index(to)] = Some(regions);
+                    to_check.push(to);
+                }
+            });
+        }
+        regions += 1;
+    }
+    region
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use board::{Board,UniformCost,Direction};
+    use board::Tile::*;
+
+    /// Follows the field from `from` until a source, and returns the nodes on the way.
+    fn follow(board: &Board,  field: &FlowField<[i32; 3]>,  from: [i32; 3]) -> Vec<[i32; 3]> {
+        let mut path = vec![from];
+        while let Some(step) = field.get(board, *path.last().unwrap()) {
+            if step.next == *path.last().unwrap() {
+                break;
+            }
+            path.push(step.next);
+        }
+        path
+    }
+
+    /// A 5x3 floor with a wall in the middle that has an opening at the bottom.
+    fn walled() -> Board {
+        let mut board = Board::new(5, 3, 1);
+        board[[2,0,0]] = Wall;
+        board[[2,1,0]] = Wall;
+        board
+    }
+
+    #[test]
+    fn paths_go_around_walls() {
+        let board = walled();
+        let (from, to) = ([0,0,0], [4,0,0]);
+        for field in &[breadth_first(&board, &UniformCost, &[to]),  dijkstra(&board, &UniformCost, &[to])] {
+            assert_eq!(field.get(&board, from).unwrap().distance, 8);
+            assert_eq!(field.get(&board, [2,0,0]), None);
+            let path = follow(&board, field, from);
+            assert_eq!(path.len(), 9);
+            assert_eq!(*path.last().unwrap(), to);
+            assert!(path.iter().all(|&p| board[p] != Wall ));
+        }
+        let (cost, path) = a_star(&board, &UniformCost, from, to).unwrap();
+        assert_eq!(cost, 8);
+        assert_eq!((path[0], path[8], path.len()), (from, to, 9));
+        assert!(path.iter().all(|&p| board[p] != Wall ));
+        assert!(path.windows(2).all(|w| UniformCost.estimate(&board, w[0], w[1]) == 1 ));
+    }
+
+    #[test]
+    fn unreachable_nodes_have_no_path() {
+        let mut board = walled();
+        board[[2,2,0]] = Wall;
+        assert_eq!(breadth_first(&board, &UniformCost, &[[4,0,0]]).get(&board, [0,0,0]), None);
+        assert_eq!(dijkstra(&board, &UniformCost, &[[4,0,0]]).get(&board, [0,0,0]), None);
+        assert_eq!(a_star(&board, &UniformCost, [0,0,0], [4,0,0]), None);
+    }
+
+    #[test]
+    fn fields_lead_to_the_nearest_source() {
+        let board = Board::new(7, 1, 1);
+        let sources = [[0,0,0], [6,0,0]];
+        for field in &[breadth_first(&board, &UniformCost, &sources),  dijkstra(&board, &UniformCost, &sources)] {
+            for x in 0..7 {
+                let step = field.get(&board, [x,0,0]).unwrap();
+                assert_eq!(step.distance, x.min(6-x) as u32, "x = {}", x);
+                let end = *follow(&board, field, [x,0,0]).last().unwrap();
+                assert!(sources.contains(&end));
+                if x != 3 {
+                    assert_eq!(end, if x < 3 {sources[0]} else {sources[1]}, "x = {}", x);
+                }
+            }
+            for &source in &sources {
+                assert_eq!(field.get(&board, source), Some(Step{distance: 0,  next: source}));
+            }
+        }
+    }
+
+    /// Entering stairs costs three, so that paths avoid them when they can.
+    struct SlowStairs;
+    impl CostModel<Board> for SlowStairs {
+        fn cost(&self,  board: &Board,  from: [i32; 3],  to: [i32; 3]) -> Option<u32> {
+            match (board[from], board[to]) {
+                (Wall, _) | (_, Wall) => None,
+                (_, Stairs) => Some(3),
+                _ => Some(1),
+            }
+        }
+        fn estimate(&self,  board: &Board,  from: [i32; 3],  to: [i32; 3]) -> u32 {
+            UniformCost.estimate(board, from, to)
+        }
+    }
+
+    #[test]
+    fn a_star_costs_the_same_as_dijkstra() {
+        // two floors connected by stairs, with more stairs in the way on the bottom one
+        let mut board = Board::new(6, 4, 2);
+        for y in 0..3 {
+            board[[3,y,0]] = Wall;
+        }
+        for p in Board::positions([1,1,0], [2,3,0]) {
+            board[p] = Stairs;
+        }
+        board[[0,3,1]] = Stairs;
+        board[[0,3,0]] = Stairs;
+        board[[5,0,1]] = Stairs;
+        board[[5,0,0]] = Stairs;
+        let to = [4,1,0];
+        let field = dijkstra(&board, &SlowStairs, &[to]);
+        for from in Board::positions([0,0,0], [5,3,1]) {
+            let expected = field.get(&board, from).map(|step| step.distance );
+            let found = a_star(&board, &SlowStairs, from, to);
+            assert_eq!(found.as_ref().map(|&(cost, _)| cost ), expected, "from {:?}", from);
+            if let Some((cost, path)) = found {
+                let total: u32 = path.windows(2).map(|w| SlowStairs.cost(&board, w[0], w[1]).unwrap() ).sum();
+                assert_eq!(total, cost, "from {:?}", from);
+            }
+        }
+        // across three stairs, as going around on the top floor is even longer
+        assert_eq!(field.get(&board, [0,0,0]).unwrap().distance, 15);
+    }
+
+    #[test]
+    fn regions_are_split_by_walls() {
+        let mut board = Board::new(7, 3, 1);
+        for y in 0..3 {
+            board[[3,y,0]] = Wall;
+        }
+        // starting from the right
+        let open: Vec<[i32; 3]> = Board::positions([0,0,0], [6,2,0]).filter(|&p| board[p] != Wall ).collect();
+        let region = regions(&board, &UniformCost, open.iter().rev().cloned());
+        for p in Board::positions([0,0,0], [6,2,0]) {
+            let expected = match p[0] {
+                0..=2 => Some(1),
+                3 => None,
+                _ => Some(0),
+            };
+            assert_eq!(region[board.index(p)], expected, "{:?}", p);
+        }
+        // an opening joins them
+        board[[3,1,0]] = Open;
+        let open = Board::positions([0,0,0], [6,2,0]).filter(|&p| board[p] != Wall );
+        let region = regions(&board, &UniformCost, open);
+        assert_eq!(region[board.index([0,0,0])], Some(0));
+        assert_eq!(region[board.index([6,2,0])], Some(0));
+        assert_eq!(region[board.index([3,0,0])], None);
+    }
+
+    #[test]
+    fn between_only_finds_neighbours() {
+        assert_eq!(Direction::between([1,1,0], [1,2,0]), Some(Direction::North));
+        assert_eq!(Direction::between([1,1,0], [0,1,0]), Some(Direction::West));
+        assert_eq!(Direction::between([1,1,1], [1,1,0]), Some(Direction::Down));
+        for &to in &[[1,1,0], [2,2,0], [3,1,0], [1,1,2], [2,1,1]] {
+            assert_eq!(Direction::between([1,1,0], to), None, "{:?}", to);
+        }
+        for &dir in &Direction::ALL {
+            let v = dir.unit_vector::<i32>();
+            assert_eq!(Direction::between([4,4,4], [4+v[0], 4+v[1], 4+v[2]]), Some(dir));
+        }
+    }
+}